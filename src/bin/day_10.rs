@@ -1,40 +1,284 @@
+use anyhow::{bail, Context, Result};
 use aoc_2020::read_entries;
-use std::usize;
+use num::{One, Zero};
+use num_bigint::BigUint;
+use std::collections::{BTreeMap, BTreeSet};
 
-fn main() {
-    let mut numbers: Vec<_> =
-        read_entries::<usize>("./data/day-10.txt").collect();
-    numbers.sort();
+struct AdapterConfig {
+    gaps: BTreeSet<usize>,
+    outlet: usize,
+    device_offset: usize,
+}
+
+impl Default for AdapterConfig {
+    fn default() -> Self {
+        Self {
+            gaps: [1, 2, 3].iter().cloned().collect(),
+            outlet: 0,
+            device_offset: 3,
+        }
+    }
+}
+
+impl AdapterConfig {
+    fn allows(&self, gap: usize) -> bool {
+        self.gaps.contains(&gap)
+    }
+
+    fn max_gap(&self) -> usize {
+        self.gaps.iter().next_back().cloned().unwrap_or(0)
+    }
+}
+
+fn main() -> Result<()> {
+    let numbers: Vec<_> = read_entries::<usize>("./data/day-10.txt").collect();
+    let config = AdapterConfig::default();
+
+    let chain = build_chain(&numbers, &config)?;
+    let histogram = gap_histogram(&chain);
+    for (gap, count) in &histogram {
+        println!("Gap {}: {}", gap, count);
+    }
+    println!("Jumps {}", get_jumps(&histogram));
+    let permutations = get_permutations(&chain, &config)?;
+    println!("Permutations {}", permutations);
+
+    if let Some(first) = Arrangements::new(&chain, &config).next() {
+        println!("First arrangement {:?}", first);
+    }
+    let last = nth_arrangement(&chain, &config, &(permutations - 1_u32))?;
+    println!("Last arrangement {:?}", last);
+    Ok(())
+}
+
+// Sorts the adapters and adds the outlet and the device at either end,
+// failing if any step of the chain isn't an allowed gap.
+fn build_chain(
+    numbers: &[usize],
+    config: &AdapterConfig,
+) -> Result<Vec<usize>> {
+    let mut chain = Vec::with_capacity(numbers.len() + 2);
+    chain.push(config.outlet);
+    chain.extend(numbers.iter().cloned());
+    chain[1..].sort_unstable();
+    let highest = *chain.last().context("Chain is never empty.")?;
+    chain.push(highest + config.device_offset);
+
+    for pair in chain.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        if to < from || !config.allows(to - from) {
+            bail!(
+                "No adapter chain possible, can't get from {} to {} with \
+                 gaps {:?}.",
+                from,
+                to,
+                config.gaps
+            );
+        }
+    }
+    Ok(chain)
+}
+
+fn gap_histogram(chain: &[usize]) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for pair in chain.windows(2) {
+        *histogram.entry(pair[1] - pair[0]).or_insert(0) += 1;
+    }
+    histogram
+}
+
+fn get_jumps(histogram: &BTreeMap<usize, usize>) -> usize {
+    let count = |gap| histogram.get(&gap).cloned().unwrap_or(0);
+    count(1) * count(3)
+}
+
+// For every adapter in the chain, the number of ways to reach the device
+// from it using only allowed gaps.
+fn path_counts(chain: &[usize], config: &AdapterConfig) -> Vec<BigUint> {
+    let mut paths = vec![BigUint::zero(); chain.len()];
+    if let Some(last) = paths.last_mut() {
+        *last = BigUint::one();
+    }
+    for i in (0..chain.len().saturating_sub(1)).rev() {
+        let mut total = BigUint::zero();
+        for j in i + 1..chain.len() {
+            let gap = chain[j] - chain[i];
+            if gap > config.max_gap() {
+                break;
+            }
+            if config.allows(gap) {
+                total += &paths[j];
+            }
+        }
+        paths[i] = total;
+    }
+    paths
+}
+
+fn get_permutations(
+    chain: &[usize],
+    config: &AdapterConfig,
+) -> Result<BigUint> {
+    let paths = path_counts(chain, config);
+    let total = paths.first().cloned().unwrap_or_else(BigUint::zero);
+    if total.is_zero() {
+        bail!("No arrangement reaches the device.");
+    }
+    Ok(total)
+}
+
+// Lazily walks every valid arrangement in lexicographic order. Adapters that
+// can't reach the device are skipped using the path counts, so every step of
+// the walk ends in an arrangement.
+struct Arrangements<'a> {
+    chain: &'a [usize],
+    config: &'a AdapterConfig,
+    paths: Vec<BigUint>,
+    stack: Vec<usize>,
+    started: bool,
+}
+
+impl<'a> Arrangements<'a> {
+    fn new(chain: &'a [usize], config: &'a AdapterConfig) -> Self {
+        Self {
+            chain,
+            config,
+            paths: path_counts(chain, config),
+            stack: vec![],
+            started: false,
+        }
+    }
 
-    println!("Jumps {}", get_jumps(&numbers));
-    println!("Permutations {}", get_permutations(&numbers));
+    fn next_step(&self, from: usize, after: usize) -> Option<usize> {
+        (after + 1..self.chain.len())
+            .take_while(|&j| {
+                self.chain[j] - self.chain[from] <= self.config.max_gap()
+            })
+            .find(|&j| {
+                self.config.allows(self.chain[j] - self.chain[from])
+                    && !self.paths[j].is_zero()
+            })
+    }
+
+    fn complete(&mut self) {
+        while let Some(&last) = self.stack.last() {
+            if last == self.chain.len() - 1 {
+                break;
+            }
+            let next = self
+                .next_step(last, last)
+                .expect("Path counts guarantee a way forward.");
+            self.stack.push(next);
+        }
+    }
 }
 
-fn get_jumps(numbers: &[usize]) -> usize {
-    let (j1, j3) = (0..numbers.len()).fold((0, 1), |(j1, j3), index| {
-        let prev = if index == 0 { 0 } else { numbers[index - 1] };
-        let cur = numbers[index];
-        match cur - prev {
-            1 => (j1 + 1, j3),
-            3 => (j1, j3 + 1),
-            _ => (j1, j3),
+impl<'a> Iterator for Arrangements<'a> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            if self.paths[0].is_zero() {
+                return None;
+            }
+            self.stack.push(0);
+        } else {
+            loop {
+                let current = self.stack.pop()?;
+                let parent = *self.stack.last()?;
+                if let Some(next) = self.next_step(parent, current) {
+                    self.stack.push(next);
+                    break;
+                }
+            }
         }
-    });
-    j1 * j3
+        self.complete();
+        Some(self.stack.iter().map(|&i| self.chain[i]).collect())
+    }
 }
 
-fn get_permutations(numbers: &[usize]) -> usize {
-    let mut paths = vec![0 as usize; numbers.len()];
-    for i in 0..numbers.len() {
-        let val = numbers[i];
-        if val <= 3 {
-            paths[i] += 1;
+// Jumps straight to the k-th (zero based) arrangement in lexicographic order
+// by skipping over whole subtrees of the path counts.
+fn nth_arrangement(
+    chain: &[usize],
+    config: &AdapterConfig,
+    k: &BigUint,
+) -> Result<Vec<usize>> {
+    let paths = path_counts(chain, config);
+    let total = paths.first().cloned().unwrap_or_else(BigUint::zero);
+    if k >= &total {
+        bail!("Only {} arrangements, can't fetch number {}.", total, k);
+    }
+
+    let mut remaining = k.clone();
+    let mut current = 0;
+    let mut arrangement = vec![chain[current]];
+    while current < chain.len() - 1 {
+        let from = chain[current];
+        let mut chosen = None;
+        for j in current + 1..chain.len() {
+            let gap = chain[j] - from;
+            if gap > config.max_gap() {
+                break;
+            }
+            if !config.allows(gap) {
+                continue;
+            }
+            if remaining < paths[j] {
+                chosen = Some(j);
+                break;
+            }
+            remaining -= &paths[j];
         }
-        let mut j = i;
-        while j > 0 && val - numbers[j - 1] <= 3 {
-            paths[i] += paths[j - 1];
-            j -= 1;
+        current = chosen.context("Path counts are inconsistent.")?;
+        arrangement.push(chain[current]);
+    }
+    Ok(arrangement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [usize; 11] = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+
+    #[test]
+    fn examples() {
+        let config = AdapterConfig::default();
+        let chain = build_chain(&EXAMPLE, &config).unwrap();
+        assert_eq!(35, get_jumps(&gap_histogram(&chain)));
+        assert_eq!(
+            BigUint::from(8_usize),
+            get_permutations(&chain, &config).unwrap()
+        );
+    }
+
+    #[test]
+    fn arrangements() {
+        let config = AdapterConfig::default();
+        let chain = build_chain(&EXAMPLE, &config).unwrap();
+        let all: Vec<_> = Arrangements::new(&chain, &config).collect();
+        assert_eq!(8, all.len());
+        assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(vec![0, 1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19, 22], all[0]);
+        for (k, arrangement) in all.iter().enumerate() {
+            assert_eq!(
+                arrangement,
+                &nth_arrangement(&chain, &config, &BigUint::from(k)).unwrap()
+            );
         }
+        assert!(
+            nth_arrangement(&chain, &config, &BigUint::from(8_usize)).is_err()
+        );
+    }
+
+    #[test]
+    fn impossible_chain() {
+        let config = AdapterConfig {
+            gaps: [1, 3].iter().cloned().collect(),
+            ..AdapterConfig::default()
+        };
+        assert!(build_chain(&[1, 3], &config).is_err());
     }
-    paths[numbers.len() - 1]
 }
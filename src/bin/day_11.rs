@@ -1,6 +1,13 @@
-use anyhow::{bail, Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use aoc_2020::read_entries;
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs,
+    hash::Hash,
+    path::Path,
+    str::FromStr,
+};
 
 const DIRECTIONS: [(isize, isize); 8] = [
     (-1, -1),
@@ -13,7 +20,7 @@ const DIRECTIONS: [(isize, isize); 8] = [
     (1, 1),
 ];
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash)]
 enum Space {
     Occupied,
     Empty,
@@ -29,78 +36,132 @@ impl Space {
             hm => bail!("What's a {}", hm),
         })
     }
+
+    fn to_char(self) -> char {
+        match self {
+            Space::Empty => 'L',
+            Space::Occupied => '#',
+            Space::Floor => '.',
+        }
+    }
+
+    fn next(self, rule: &impl Rule, occupied_neighbors: usize) -> Self {
+        match (self, occupied_neighbors) {
+            (Space::Occupied, o) if !rule.survives(o) => Space::Empty,
+            (Space::Empty, o) if rule.born(o) => Space::Occupied,
+            (space, _) => space,
+        }
+    }
 }
 
-#[derive(PartialEq, Clone)]
-struct Seating {
-    spaces: Vec<Vec<Space>>,
+// Decides what happens to a seat given how many of its neighbours are
+// occupied.
+trait Rule {
+    fn born(&self, occupied_neighbors: usize) -> bool;
+    fn survives(&self, occupied_neighbors: usize) -> bool;
 }
 
-impl Seating {
-    fn width(&self) -> usize {
-        self.spaces[0].len()
-    }
+// The puzzle's rule: people sit down when nobody is around and leave once
+// `tolerance` neighbours are occupied.
+struct Tolerance(usize);
 
-    fn height(&self) -> usize {
-        self.spaces.len()
+impl Rule for Tolerance {
+    fn born(&self, occupied_neighbors: usize) -> bool {
+        occupied_neighbors == 0
     }
 
-    fn next(&self, tolerance: usize, visible: &[Space]) -> Self {
-        let mut spaces = vec![vec![Space::Floor; self.width()]; self.height()];
-        for x in 0..self.height() {
-            for y in 0..self.width() {
-                spaces[x][y] = self.next_state(x, y, tolerance, visible);
-            }
-        }
-        Seating { spaces }
+    fn survives(&self, occupied_neighbors: usize) -> bool {
+        occupied_neighbors < self.0
     }
+}
 
-    fn next_state(
+// Decides which seats count as neighbours of a given seat.
+trait Neighborhood {
+    fn neighbors(
         &self,
+        seating: &Seating,
         x: usize,
         y: usize,
-        tolerance: usize,
-        visible: &[Space],
-    ) -> Space {
-        let occupied_neighbors = self.occupied_neighbors(x, y, visible);
-        match (self.spaces[x][y], occupied_neighbors) {
-            (Space::Occupied, o) if o >= tolerance => Space::Empty,
-            (Space::Empty, 0) => Space::Occupied,
-            (space, _) => space,
+    ) -> Vec<(usize, usize)>;
+}
+
+// Every seat within the given number of steps in any direction, so
+// `Radius(1)` is the eight adjacent spaces.
+struct Radius(usize);
+
+impl Neighborhood for Radius {
+    fn neighbors(
+        &self,
+        seating: &Seating,
+        x: usize,
+        y: usize,
+    ) -> Vec<(usize, usize)> {
+        let radius = self.0;
+        let mut neighbors = vec![];
+        for nx in x.saturating_sub(radius)..=(x + radius) {
+            for ny in y.saturating_sub(radius)..=(y + radius) {
+                if (nx, ny) != (x, y)
+                    && seating.in_bounds(nx as isize, ny as isize)
+                    && seating.spaces[nx][ny] != Space::Floor
+                {
+                    neighbors.push((nx, ny));
+                }
+            }
         }
+        neighbors
     }
+}
+
+// The first seat visible in each of the eight directions, looking past
+// floor.
+struct LineOfSight;
 
-    fn occupied_neighbors(
+impl Neighborhood for LineOfSight {
+    fn neighbors(
         &self,
+        seating: &Seating,
         x: usize,
         y: usize,
-        visible: &[Space],
-    ) -> usize {
+    ) -> Vec<(usize, usize)> {
         DIRECTIONS
             .iter()
-            .map(|direction| self.first_in_direction(direction, x, y, visible))
-            .filter(|&s| s == Space::Occupied)
-            .count()
+            .filter_map(|direction| {
+                seating.first_seat_in_direction(direction, x, y)
+            })
+            .collect()
     }
+}
 
-    fn first_in_direction(
+#[derive(PartialEq, Eq, Clone, Hash)]
+struct Seating {
+    spaces: Vec<Vec<Space>>,
+}
+
+impl Seating {
+    fn width(&self) -> usize {
+        self.spaces[0].len()
+    }
+
+    fn height(&self) -> usize {
+        self.spaces.len()
+    }
+
+    fn first_seat_in_direction(
         &self,
         (dx, dy): &(isize, isize),
         x: usize,
         y: usize,
-        visible: &[Space],
-    ) -> Space {
+    ) -> Option<(usize, usize)> {
         let (mut pos_x, mut pos_y) = (x as isize, y as isize);
         loop {
-            pos_x = pos_x + dx;
-            pos_y = pos_y + dy;
-            if self.in_bounds(pos_x, pos_y) {
-                let space = self.spaces[pos_x as usize][pos_y as usize];
-                if visible.contains(&space) {
-                    return space;
-                }
-            } else {
-                return Space::Floor;
+            pos_x += dx;
+            pos_y += dy;
+            if !self.in_bounds(pos_x, pos_y) {
+                return None;
+            }
+            let (seat_x, seat_y) = (pos_x as usize, pos_y as usize);
+            if self.spaces[seat_x][seat_y] != Space::Floor {
+                return Some((seat_x, seat_y));
             }
         }
     }
@@ -120,6 +181,186 @@ impl Seating {
     }
 }
 
+// Floor never changes, so which seats see which only depends on the initial
+// layout. Seats are numbered once and every seat keeps both the seats it
+// watches and the seats watching it.
+struct Layout {
+    seats: Vec<(usize, usize)>,
+    neighbors: Vec<Vec<usize>>,
+    watchers: Vec<Vec<usize>>,
+}
+
+impl Layout {
+    fn new(seating: &Seating, neighborhood: &impl Neighborhood) -> Self {
+        let mut index = vec![vec![None; seating.width()]; seating.height()];
+        let mut seats = vec![];
+        for (x, row) in seating.spaces.iter().enumerate() {
+            for (y, space) in row.iter().enumerate() {
+                if *space != Space::Floor {
+                    index[x][y] = Some(seats.len());
+                    seats.push((x, y));
+                }
+            }
+        }
+
+        let neighbors: Vec<Vec<usize>> = seats
+            .iter()
+            .map(|&(x, y)| {
+                neighborhood
+                    .neighbors(seating, x, y)
+                    .iter()
+                    .filter_map(|&(nx, ny)| index[nx][ny])
+                    .collect()
+            })
+            .collect();
+
+        let mut watchers = vec![vec![]; seats.len()];
+        for (seat, seat_neighbors) in neighbors.iter().enumerate() {
+            for &neighbor in seat_neighbors {
+                watchers[neighbor].push(seat);
+            }
+        }
+
+        Self {
+            seats,
+            neighbors,
+            watchers,
+        }
+    }
+}
+
+// Steps the seating one generation at a time, only revisiting seats that
+// changed or that watch a seat which changed. Occupied neighbour counts are
+// kept up to date as seats flip instead of being recounted.
+struct Simulation<'a, R: Rule> {
+    rule: &'a R,
+    layout: Layout,
+    seating: Seating,
+    occupied_neighbors: Vec<usize>,
+    pending: Vec<usize>,
+    queued: Vec<bool>,
+}
+
+impl<'a, R: Rule> Simulation<'a, R> {
+    fn new(
+        seating: &Seating,
+        rule: &'a R,
+        neighborhood: &impl Neighborhood,
+    ) -> Self {
+        let layout = Layout::new(seating, neighborhood);
+        let occupied_neighbors = layout
+            .neighbors
+            .iter()
+            .map(|seat_neighbors| {
+                seat_neighbors
+                    .iter()
+                    .filter(|&&n| {
+                        let (x, y) = layout.seats[n];
+                        seating.spaces[x][y] == Space::Occupied
+                    })
+                    .count()
+            })
+            .collect();
+        let seat_count = layout.seats.len();
+        Self {
+            rule,
+            layout,
+            seating: seating.clone(),
+            occupied_neighbors,
+            pending: (0..seat_count).collect(),
+            queued: vec![true; seat_count],
+        }
+    }
+
+    fn space(&self, seat: usize) -> Space {
+        let (x, y) = self.layout.seats[seat];
+        self.seating.spaces[x][y]
+    }
+
+    // Advances one generation, returning whether any seat changed.
+    fn step(&mut self) -> bool {
+        let changes: Vec<(usize, Space)> = self
+            .pending
+            .iter()
+            .filter_map(|&seat| {
+                let current = self.space(seat);
+                let next =
+                    current.next(self.rule, self.occupied_neighbors[seat]);
+                if next != current {
+                    Some((seat, next))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for &seat in &self.pending {
+            self.queued[seat] = false;
+        }
+        self.pending.clear();
+
+        for &(seat, space) in &changes {
+            let (x, y) = self.layout.seats[seat];
+            self.seating.spaces[x][y] = space;
+            self.enqueue(seat);
+            for i in 0..self.layout.watchers[seat].len() {
+                let watcher = self.layout.watchers[seat][i];
+                if space == Space::Occupied {
+                    self.occupied_neighbors[watcher] += 1;
+                } else {
+                    self.occupied_neighbors[watcher] -= 1;
+                }
+                self.enqueue(watcher);
+            }
+        }
+        !changes.is_empty()
+    }
+
+    fn enqueue(&mut self, seat: usize) {
+        if !self.queued[seat] {
+            self.queued[seat] = true;
+            self.pending.push(seat);
+        }
+    }
+}
+
+impl Display for Seating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.spaces {
+            writeln!(
+                f,
+                "{}",
+                row.iter().map(|s| s.to_char()).collect::<String>()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    // Nothing changes any more after this many generations.
+    Stable(usize),
+    // The seating at generation `start` comes back every `period`
+    // generations.
+    Cycle { start: usize, period: usize },
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Stable(generations) => {
+                write!(f, "stable after {} generations", generations)
+            }
+            Outcome::Cycle { start, period } => write!(
+                f,
+                "cycles with period {} from generation {}",
+                period, start
+            ),
+        }
+    }
+}
+
 struct Entry(Vec<Space>);
 
 impl FromStr for Entry {
@@ -134,28 +375,139 @@ impl FromStr for Entry {
     }
 }
 
-fn main() {
+fn main() -> Result<()> {
+    let frames = std::env::args().nth(1);
+    let frames = frames.as_ref().map(Path::new);
     let spaces: Vec<Vec<_>> = read_entries::<Entry>("./data/day-11.txt")
         .map(|Entry(spaces)| spaces)
         .collect();
     let initial_seating = Seating { spaces };
-    let all_spaces = &[Space::Occupied, Space::Floor, Space::Empty];
-    let just_chairs = &[Space::Occupied, Space::Empty];
-    run_simulation(&initial_seating, 4, all_spaces);
-    run_simulation(&initial_seating, 5, just_chairs);
+
+    let (outcome, seating) = run_simulation(
+        &initial_seating,
+        &Tolerance(4),
+        &Radius(1),
+        frames.map(|dir| dir.join("adjacent")).as_deref(),
+    )?;
+    println!(
+        "There are {} occupied seats, {}.",
+        seating.count_occupied(),
+        outcome
+    );
+
+    let (outcome, seating) = run_simulation(
+        &initial_seating,
+        &Tolerance(5),
+        &LineOfSight,
+        frames.map(|dir| dir.join("line-of-sight")).as_deref(),
+    )?;
+    println!(
+        "There are {} occupied seats, {}.",
+        seating.count_occupied(),
+        outcome
+    );
+    Ok(())
 }
 
-fn run_simulation(seating: &Seating, tolerance: usize, visible: &[Space]) {
-    let mut current_seating = seating.clone();
+// Runs until the seating stops changing or a generation repeats, optionally
+// writing every generation as a text frame into `frames`.
+fn run_simulation(
+    seating: &Seating,
+    rule: &impl Rule,
+    neighborhood: &impl Neighborhood,
+    frames: Option<&Path>,
+) -> Result<(Outcome, Seating)> {
+    if let Some(dir) = frames {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+
+    let mut simulation = Simulation::new(seating, rule, neighborhood);
+    let mut seen = HashMap::new();
+    let mut generation = 0;
     loop {
-        let next = current_seating.next(tolerance, visible);
-        if next == current_seating {
-            break;
+        if let Some(dir) = frames {
+            let path = dir.join(format!("{:05}.txt", generation));
+            fs::write(&path, simulation.seating.to_string())
+                .with_context(|| format!("Failed to write {:?}", path))?;
         }
-        current_seating = next;
+
+        // Keyed by the whole seating rather than a hash of it, so a
+        // collision can't pass for a cycle.
+        if let Some(start) = seen.insert(simulation.seating.clone(), generation)
+        {
+            let period = generation - start;
+            return Ok((Outcome::Cycle { start, period }, simulation.seating));
+        }
+
+        if !simulation.step() {
+            return Ok((Outcome::Stable(generation), simulation.seating));
+        }
+        generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "L.LL.LL.LL
+LLLLLLL.LL
+L.L.L..L..
+LLLL.LL.LL
+L.LL.LL.LL
+L.LLLLL.LL
+..L.L.....
+LLLLLLLLLL
+L.LLLLLL.L
+L.LLLLL.LL";
+
+    fn example() -> Seating {
+        let spaces = EXAMPLE
+            .lines()
+            .map(|line| line.parse::<Entry>().unwrap().0)
+            .collect();
+        Seating { spaces }
+    }
+
+    #[test]
+    fn examples() {
+        let (outcome, seating) =
+            run_simulation(&example(), &Tolerance(4), &Radius(1), None)
+                .unwrap();
+        assert_eq!(Outcome::Stable(5), outcome);
+        assert_eq!(37, seating.count_occupied());
+
+        let (outcome, seating) =
+            run_simulation(&example(), &Tolerance(5), &LineOfSight, None)
+                .unwrap();
+        assert_eq!(Outcome::Stable(6), outcome);
+        assert_eq!(26, seating.count_occupied());
+    }
+
+    // Every seat flips every generation.
+    struct Blink;
+
+    impl Rule for Blink {
+        fn born(&self, _: usize) -> bool {
+            true
+        }
+
+        fn survives(&self, _: usize) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let (outcome, _) =
+            run_simulation(&example(), &Blink, &Radius(1), None).unwrap();
+        assert_eq!(
+            Outcome::Cycle {
+                start: 0,
+                period: 2
+            },
+            outcome
+        );
     }
-    println!(
-        "There are {} occupied seats.",
-        current_seating.count_occupied()
-    );
 }
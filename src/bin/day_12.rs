@@ -1,6 +1,6 @@
 use anyhow::{bail, Context, Error, Result};
-use aoc_2020::read_entries;
-use std::str::FromStr;
+use aoc_2020::{flag_value, read_entries};
+use std::{fs, path::Path, str::FromStr};
 
 #[derive(Clone, Copy)]
 enum Relative {
@@ -15,28 +15,85 @@ impl Relative {
             Relative::Right => (y, -x),
         }
     }
+
+    // Rotates a point by an eighth of a turn by walking it along the square
+    // ring it sits on, so two eighths make exactly one right angle and the
+    // compass offsets map onto the diagonal ones.
+    fn eighth_offset(&self, point: (isize, isize)) -> (isize, isize) {
+        let radius = point.0.abs().max(point.1.abs());
+        if radius == 0 {
+            return point;
+        }
+        let perimeter = 8 * radius;
+        let index = ring_index(point, radius);
+        let index = match self {
+            Relative::Left => index + radius,
+            Relative::Right => index + perimeter - radius,
+        };
+        ring_point(index % perimeter, radius)
+    }
+}
+
+// Position of a point along its square ring, counter-clockwise from the
+// bottom right corner.
+fn ring_index((x, y): (isize, isize), radius: isize) -> isize {
+    if x == radius && y < radius {
+        y + radius
+    } else if y == radius && x > -radius {
+        2 * radius + (radius - x)
+    } else if x == -radius && y > -radius {
+        4 * radius + (radius - y)
+    } else {
+        6 * radius + (x + radius)
+    }
+}
+
+fn ring_point(index: isize, radius: isize) -> (isize, isize) {
+    let offset = index % (2 * radius);
+    match index / (2 * radius) {
+        0 => (radius, -radius + offset),
+        1 => (radius - offset, radius),
+        2 => (-radius, radius - offset),
+        _ => (-radius + offset, -radius),
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Compass {
     North,
     South,
     East,
     West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
 }
 
+// Every heading in counter-clockwise order, an eighth of a turn apart.
+const HEADINGS: [Compass; 8] = [
+    Compass::East,
+    Compass::NorthEast,
+    Compass::North,
+    Compass::NorthWest,
+    Compass::West,
+    Compass::SouthWest,
+    Compass::South,
+    Compass::SouthEast,
+];
+
 impl Compass {
-    fn relative_to(&self, relative: &Relative) -> Compass {
-        match (relative, self) {
-            (Relative::Left, Compass::North) => Compass::West,
-            (Relative::Left, Compass::West) => Compass::South,
-            (Relative::Left, Compass::South) => Compass::East,
-            (Relative::Left, Compass::East) => Compass::North,
-            (Relative::Right, Compass::North) => Compass::East,
-            (Relative::Right, Compass::East) => Compass::South,
-            (Relative::Right, Compass::South) => Compass::West,
-            (Relative::Right, Compass::West) => Compass::North,
-        }
+    fn turned(&self, relative: &Relative, eighths: usize) -> Compass {
+        let index = HEADINGS
+            .iter()
+            .position(|heading| heading == self)
+            .expect("Every heading is listed.");
+        let eighths = eighths % HEADINGS.len();
+        let index = match relative {
+            Relative::Left => index + eighths,
+            Relative::Right => index + HEADINGS.len() - eighths,
+        };
+        HEADINGS[index % HEADINGS.len()]
     }
 
     fn offset(&self) -> (isize, isize) {
@@ -45,6 +102,27 @@ impl Compass {
             Compass::South => (0, -1),
             Compass::East => (1, 0),
             Compass::West => (-1, 0),
+            Compass::NorthEast => (1, 1),
+            Compass::NorthWest => (-1, 1),
+            Compass::SouthEast => (1, -1),
+            Compass::SouthWest => (-1, -1),
+        }
+    }
+}
+
+// How finely the ship can turn. The puzzle only ever turns by right angles,
+// the eight heading variant also allows the diagonals in between.
+#[derive(Clone, Copy)]
+enum Headings {
+    Four,
+    Eight,
+}
+
+impl Headings {
+    fn degrees(&self) -> usize {
+        match self {
+            Headings::Four => 90,
+            Headings::Eight => 45,
         }
     }
 }
@@ -60,10 +138,8 @@ struct Instruction {
     amount: usize,
 }
 
-impl FromStr for Instruction {
-    type Err = Error;
-
-    fn from_str(input: &str) -> Result<Self> {
+impl Instruction {
+    fn parse(input: &str, headings: Headings) -> Result<Self> {
         let direction =
             match input.chars().next().context("Must start with a char")? {
                 'N' => Direction::Absolute(Compass::North),
@@ -75,13 +151,55 @@ impl FromStr for Instruction {
                 'F' => Direction::Forward,
                 hm => bail!("What is {}?", hm),
             };
-        let amount = input[1..]
+        let mut amount = input[1..]
             .parse::<usize>()
             .context("Failed to parse amount")?;
+        if let Direction::Relative(_) = direction {
+            if amount % headings.degrees() != 0 {
+                bail!(
+                    "Can't turn by {} degrees, turns must be multiples of {}.",
+                    amount,
+                    headings.degrees()
+                );
+            }
+            amount %= 360;
+        }
         Ok(Self { direction, amount })
     }
 }
 
+impl FromStr for Instruction {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Self::parse(input, Headings::Four)
+    }
+}
+
+// One interpretation of the instruction stream. Implementors only say what
+// each kind of instruction does to them.
+trait Navigator {
+    fn name(&self) -> &'static str;
+    fn position(&self) -> (isize, isize);
+
+    fn waypoint(&self) -> Option<(isize, isize)> {
+        None
+    }
+
+    fn absolute(&mut self, compass: Compass, amount: usize);
+    fn relative(&mut self, direction: Relative, degrees: usize);
+    fn forward(&mut self, amount: usize);
+
+    fn execute(&mut self, instruction: &Instruction) {
+        let amount = instruction.amount;
+        match &instruction.direction {
+            Direction::Absolute(compass) => self.absolute(*compass, amount),
+            Direction::Relative(direction) => self.relative(*direction, amount),
+            Direction::Forward => self.forward(amount),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Ship {
     position: (isize, isize),
@@ -95,26 +213,28 @@ impl Ship {
             facing_direction: Compass::East,
         }
     }
+}
 
-    fn execute(&mut self, instruction: &Instruction) {
-        let amount = instruction.amount;
-        match &instruction.direction {
-            Direction::Absolute(compass) => self.move_ship(*compass, amount),
-            Direction::Forward => self.move_ship(self.facing_direction, amount),
-            Direction::Relative(direction) => self.turn(direction, amount),
-        }
+impl Navigator for Ship {
+    fn name(&self) -> &'static str {
+        "ship"
     }
 
-    fn move_ship(&mut self, compass: Compass, amount: usize) {
+    fn position(&self) -> (isize, isize) {
+        self.position
+    }
+
+    fn absolute(&mut self, compass: Compass, amount: usize) {
         self.position = move_point(self.position, compass.offset(), amount);
     }
 
-    fn turn(&mut self, direction: &Relative, mut amount: usize) {
-        while amount > 0 {
-            self.facing_direction =
-                self.facing_direction.relative_to(&direction);
-            amount -= 90;
-        }
+    fn relative(&mut self, direction: Relative, degrees: usize) {
+        self.facing_direction =
+            self.facing_direction.turned(&direction, degrees / 45);
+    }
+
+    fn forward(&mut self, amount: usize) {
+        self.absolute(self.facing_direction, amount);
     }
 }
 
@@ -130,36 +250,103 @@ impl ShipWithWaypoint {
             waypoint: (10, 1),
         }
     }
+}
 
-    fn execute(&mut self, instruction: &Instruction) {
-        let amount = instruction.amount;
-        match &instruction.direction {
-            Direction::Absolute(compass) => {
-                self.move_waypoint(*compass, amount)
-            }
-            Direction::Forward => self.move_ship(amount),
-            Direction::Relative(direction) => {
-                self.rotate_waypoint(*direction, amount)
-            }
-        }
+impl Navigator for ShipWithWaypoint {
+    fn name(&self) -> &'static str {
+        "waypoint"
     }
 
-    fn move_waypoint(&mut self, compass: Compass, amount: usize) {
+    fn position(&self) -> (isize, isize) {
+        self.position
+    }
+
+    fn waypoint(&self) -> Option<(isize, isize)> {
+        Some(self.waypoint)
+    }
+
+    fn absolute(&mut self, compass: Compass, amount: usize) {
         self.waypoint = move_point(self.waypoint, compass.offset(), amount);
     }
 
-    fn rotate_waypoint(&mut self, direction: Relative, mut amount: usize) {
-        while amount > 0 {
-            self.waypoint = direction.relative_offset(self.waypoint);
-            amount -= 90;
-        }
+    fn relative(&mut self, direction: Relative, degrees: usize) {
+        self.waypoint = rotate(self.waypoint, direction, degrees);
     }
 
-    fn move_ship(&mut self, times: usize) {
+    fn forward(&mut self, times: usize) {
         self.position = move_point(self.position, self.waypoint, times)
     }
 }
 
+// Like `ShipWithWaypoint`, but the waypoint is kept in the ship's own frame:
+// compass instructions are read as if the ship still faced east, so north
+// always nudges the waypoint to port, and turning swings the ship together
+// with its waypoint.
+struct RelativeWaypoint {
+    position: (isize, isize),
+    facing_direction: Compass,
+    waypoint: (isize, isize),
+}
+
+impl RelativeWaypoint {
+    fn new() -> Self {
+        Self {
+            position: (0, 0),
+            facing_direction: Compass::East,
+            waypoint: (10, 1),
+        }
+    }
+
+    fn world_waypoint(&self) -> (isize, isize) {
+        let eighths = HEADINGS
+            .iter()
+            .position(|heading| *heading == self.facing_direction)
+            .expect("Every heading is listed.");
+        rotate(self.waypoint, Relative::Left, eighths * 45)
+    }
+}
+
+impl Navigator for RelativeWaypoint {
+    fn name(&self) -> &'static str {
+        "relative-waypoint"
+    }
+
+    fn position(&self) -> (isize, isize) {
+        self.position
+    }
+
+    fn waypoint(&self) -> Option<(isize, isize)> {
+        Some(self.world_waypoint())
+    }
+
+    fn absolute(&mut self, compass: Compass, amount: usize) {
+        self.waypoint = move_point(self.waypoint, compass.offset(), amount);
+    }
+
+    fn relative(&mut self, direction: Relative, degrees: usize) {
+        self.facing_direction =
+            self.facing_direction.turned(&direction, degrees / 45);
+    }
+
+    fn forward(&mut self, times: usize) {
+        self.position = move_point(self.position, self.world_waypoint(), times)
+    }
+}
+
+fn rotate(
+    mut point: (isize, isize),
+    direction: Relative,
+    degrees: usize,
+) -> (isize, isize) {
+    for _ in 0..degrees / 90 {
+        point = direction.relative_offset(point);
+    }
+    if degrees % 90 == 45 {
+        point = direction.eighth_offset(point);
+    }
+    point
+}
+
 fn move_point(
     (x, y): (isize, isize),
     (dx, dy): (isize, isize),
@@ -175,16 +362,213 @@ fn manhattan_distance((x, y): (isize, isize)) -> isize {
     x.abs() + y.abs()
 }
 
-fn main() {
-    let instructions: Vec<_> =
-        read_entries::<Instruction>("./data/day-12.txt").collect();
+// The waypoint is only there for ships that steer by one.
+struct Step {
+    position: (isize, isize),
+    waypoint: Option<(isize, isize)>,
+}
+
+// Where a ship is after each instruction, starting from where it set off.
+#[derive(Default)]
+struct Voyage {
+    steps: Vec<Step>,
+}
 
-    let mut ship = Ship::new();
-    let mut ship_waypoint = ShipWithWaypoint::new();
+impl Voyage {
+    fn record(
+        &mut self,
+        position: (isize, isize),
+        waypoint: Option<(isize, isize)>,
+    ) {
+        self.steps.push(Step { position, waypoint });
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("step,x,y,waypoint_x,waypoint_y\n");
+        for (i, step) in self.steps.iter().enumerate() {
+            let (x, y) = step.position;
+            let (wx, wy) = match step.waypoint {
+                Some((wx, wy)) => (wx.to_string(), wy.to_string()),
+                None => (String::new(), String::new()),
+            };
+            csv.push_str(&format!("{},{},{},{},{}\n", i, x, y, wx, wy));
+        }
+        csv
+    }
+}
+
+const ROUTE_COLORS: [&str; 4] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd"];
+
+// Draws every voyage as a line on one plot with north pointing up, marking
+// where they all set off from.
+fn render_svg(voyages: &[(&str, &Voyage)]) -> String {
+    let points = || {
+        voyages
+            .iter()
+            .flat_map(|(_, voyage)| voyage.steps.iter().map(|s| s.position))
+    };
+    let min_x = points().map(|(x, _)| x).min().unwrap_or(0);
+    let max_x = points().map(|(x, _)| x).max().unwrap_or(0);
+    let min_y = points().map(|(_, y)| y).min().unwrap_or(0);
+    let max_y = points().map(|(_, y)| y).max().unwrap_or(0);
+    let margin = ((max_x - min_x).max(max_y - min_y) / 20).max(1);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+         viewBox=\"{} {} {} {}\" width=\"800\" height=\"800\">\n",
+        min_x - margin,
+        -max_y - margin,
+        max_x - min_x + 2 * margin,
+        max_y - min_y + 2 * margin
+    );
+    for (i, (name, voyage)) in voyages.iter().enumerate() {
+        let path = voyage
+            .steps
+            .iter()
+            .map(|step| format!("{},{}", step.position.0, -step.position.1))
+            .collect::<Vec<_>>()
+            .join(" ");
+        svg.push_str(&format!(
+            "  <polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" \
+             vector-effect=\"non-scaling-stroke\" points=\"{}\">\
+             <title>{}</title></polyline>\n",
+            ROUTE_COLORS[i % ROUTE_COLORS.len()],
+            path,
+            name
+        ));
+    }
+    svg.push_str(&format!(
+        "  <circle cx=\"0\" cy=\"0\" r=\"{}\" fill=\"black\"/>\n</svg>\n",
+        margin / 2 + 1
+    ));
+    svg
+}
+
+fn write_trace(dir: &Path, voyages: &[(&str, &Voyage)]) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {:?}", dir))?;
+    for (name, voyage) in voyages {
+        let path = dir.join(format!("{}.csv", name));
+        fs::write(&path, voyage.to_csv())
+            .with_context(|| format!("Failed to write {:?}", path))?;
+    }
+    let path = dir.join("route.svg");
+    fs::write(&path, render_svg(voyages))
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let headings = if args.iter().any(|arg| arg == "--eight-headings") {
+        Headings::Eight
+    } else {
+        Headings::Four
+    };
+    let trace_dir = flag_value(&args, "--trace")
+        .map(|dir| dir.context("--trace needs a directory."))
+        .transpose()?;
+    let instructions = read_entries::<String>("./data/day-12.txt")
+        .map(|line| Instruction::parse(&line, headings))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut navigators: Vec<Box<dyn Navigator>> = vec![
+        Box::new(Ship::new()),
+        Box::new(ShipWithWaypoint::new()),
+        Box::new(RelativeWaypoint::new()),
+    ];
+    let mut voyages: Vec<Voyage> = navigators
+        .iter()
+        .map(|navigator| {
+            let mut voyage = Voyage::default();
+            voyage.record(navigator.position(), navigator.waypoint());
+            voyage
+        })
+        .collect();
     for instruction in &instructions {
-        ship.execute(instruction);
-        ship_waypoint.execute(instruction);
+        for (navigator, voyage) in navigators.iter_mut().zip(&mut voyages) {
+            navigator.execute(instruction);
+            voyage.record(navigator.position(), navigator.waypoint());
+        }
+    }
+    for navigator in &navigators {
+        println!(
+            "Distance {:?} ({})",
+            manhattan_distance(navigator.position()),
+            navigator.name()
+        );
+    }
+
+    if let Some(dir) = trace_dir {
+        let named: Vec<_> = navigators
+            .iter()
+            .map(|navigator| navigator.name())
+            .zip(&voyages)
+            .collect();
+        write_trace(Path::new(dir), &named)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [&str; 5] = ["F10", "N3", "F7", "R90", "F11"];
+
+    #[test]
+    fn examples() {
+        let mut ship = Ship::new();
+        let mut ship_waypoint = ShipWithWaypoint::new();
+        let mut relative_waypoint = RelativeWaypoint::new();
+        for line in &EXAMPLE {
+            let instruction = line.parse::<Instruction>().unwrap();
+            ship.execute(&instruction);
+            ship_waypoint.execute(&instruction);
+            relative_waypoint.execute(&instruction);
+        }
+        assert_eq!(25, manhattan_distance(ship.position));
+        assert_eq!(286, manhattan_distance(ship_waypoint.position));
+        // N3 leaves the waypoint at 10 ahead and 4 to port, which R90 swings
+        // round to 10 south and 4 east.
+        assert_eq!((214, -72), relative_waypoint.position);
+    }
+
+    #[test]
+    fn voyage_csv() {
+        let mut voyage = Voyage::default();
+        voyage.record((0, 0), Some((10, 1)));
+        voyage.record((100, 10), Some((10, 1)));
+        assert_eq!(
+            "step,x,y,waypoint_x,waypoint_y\n0,0,0,10,1\n1,100,10,10,1\n",
+            voyage.to_csv()
+        );
+    }
+
+    #[test]
+    fn turn_validation() {
+        assert!("R30".parse::<Instruction>().is_err());
+        assert!("L45".parse::<Instruction>().is_err());
+        assert!(Instruction::parse("L45", Headings::Eight).is_ok());
+        assert_eq!(90, "R450".parse::<Instruction>().unwrap().amount);
+    }
+
+    #[test]
+    fn eighth_turns() {
+        assert_eq!(
+            Compass::SouthEast,
+            Compass::East.turned(&Relative::Right, 1)
+        );
+        assert_eq!(
+            Compass::West,
+            Compass::NorthEast.turned(&Relative::Left, 3)
+        );
+        for &point in &[(10, 4), (-3, 7), (0, -5), (2, 2)] {
+            for &relative in &[Relative::Left, Relative::Right] {
+                assert_eq!(
+                    relative.relative_offset(point),
+                    relative.eighth_offset(relative.eighth_offset(point))
+                );
+            }
+        }
     }
-    println!("Distance {:?}", manhattan_distance(ship.position));
-    println!("Distance {:?}", manhattan_distance(ship_waypoint.position));
 }
@@ -1,5 +1,8 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use aoc_2020::read_entries;
+use num::{integer::ExtendedGcd, Integer, Zero};
+use num_bigint::BigInt;
+use std::{cmp::Reverse, collections::BinaryHeap};
 
 fn main() -> Result<()> {
     let mut entries = read_entries::<String>("./data/day-13.txt");
@@ -9,59 +12,183 @@ fn main() -> Result<()> {
         .parse::<usize>()
         .context("Unparseable timestamp.")?;
     let raw_ids = entries.next().context("Missing timetables.")?;
-    let bus_ids: Vec<_> = raw_ids.split(",").collect();
-    let res = next_departure(timestamp, &bus_ids)?;
+    let bus_ids: Vec<_> = raw_ids.split(',').collect();
+    let timetable = Timetable::parse(&bus_ids)?;
+
+    let res = next_departure(timestamp, &timetable)?;
     println!("Result part 1 {}", res);
-    let res = golden_coin(&bus_ids);
+    for (time, bus_id) in timetable.departures_after(timestamp, 5) {
+        println!("Bus {} leaves at {}", bus_id, time);
+    }
+
+    let first_three: Vec<_> =
+        timetable.buses.iter().take(3).map(|(_, id)| *id).collect();
+    let time = timetable.first_window(&first_three, 10, timestamp)?;
+    println!("Buses {:?} all leave within 10 of {}", first_three, time);
+
+    let res = golden_coin(&bus_ids)?;
     println!("Result part 2 {}", res);
+    let res = timetable.pattern_after(&BigInt::from(timestamp))?;
+    println!("Next offset pattern after {} starts at {}", timestamp, res);
     Ok(())
 }
 
-fn next_departure(timestamp: usize, bus_ids: &[&str]) -> Result<usize> {
-    let (bus_id, time_to_wait) = bus_ids
-        .iter()
-        .filter(|&&bus_id| bus_id != "x")
-        .map(|bus_str| bus_str.parse::<usize>().context("Unparseable bus id."))
-        .flatten()
-        .map(|bus_id| (bus_id, time_to_wait(timestamp, bus_id)))
-        .min_by_key(|(_, time_to_wait)| *time_to_wait)
-        .context("Iterator empty")?;
+struct Timetable {
+    // The buses in service along with their offset in the timetable.
+    buses: Vec<(usize, usize)>,
+}
+
+impl Timetable {
+    fn parse(bus_ids: &[&str]) -> Result<Self> {
+        let buses = bus_ids
+            .iter()
+            .enumerate()
+            .filter(|(_, &raw)| raw != "x")
+            .map(|(offset, raw)| {
+                let id = raw.parse::<usize>().context("Unparseable bus id.")?;
+                if id == 0 {
+                    bail!("Bus id can't be 0.");
+                }
+                Ok((offset, id))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { buses })
+    }
+
+    // The next `count` departures of any bus at or after `timestamp`, as
+    // (time, bus id) pairs in the order they leave.
+    fn departures_after(
+        &self,
+        timestamp: usize,
+        count: usize,
+    ) -> Vec<(usize, usize)> {
+        let mut queue: BinaryHeap<_> = self
+            .buses
+            .iter()
+            .map(|&(_, id)| {
+                Reverse((timestamp + time_to_wait(timestamp, id), id))
+            })
+            .collect();
+        let mut departures = Vec::with_capacity(count);
+        while departures.len() < count {
+            let Reverse((time, id)) = match queue.pop() {
+                Some(next) => next,
+                None => break,
+            };
+            departures.push((time, id));
+            queue.push(Reverse((time + id, id)));
+        }
+        departures
+    }
+
+    // The first time at or after `timestamp` from which every bus in
+    // `bus_ids` leaves within `window` minutes.
+    fn first_window(
+        &self,
+        bus_ids: &[usize],
+        window: usize,
+        timestamp: usize,
+    ) -> Result<usize> {
+        if bus_ids.is_empty() {
+            bail!("Need at least one bus to wait for.");
+        }
+        if let Some(id) = bus_ids
+            .iter()
+            .find(|id| !self.buses.iter().any(|(_, bus)| bus == *id))
+        {
+            bail!("Bus {} isn't in the timetable.", id);
+        }
+
+        // The window closes with the last of the buses leaving, so try
+        // every departure in order as the close and open it as early as
+        // that allows. The opening time never goes down as the close moves
+        // later, so the first window found is the earliest. Every bus leaves
+        // together at each multiple of the lcm of their ids, so there always
+        // is one.
+        let mut queue: BinaryHeap<_> = bus_ids
+            .iter()
+            .map(|&id| Reverse((timestamp + time_to_wait(timestamp, id), id)))
+            .collect();
+        loop {
+            let Reverse((close, id)) =
+                queue.pop().expect("Every bus leaves again.");
+            let start = close.saturating_sub(window).max(timestamp);
+            if bus_ids.iter().all(|&id| time_to_wait(start, id) <= window) {
+                return Ok(start);
+            }
+            queue.push(Reverse((close + id, id)));
+        }
+    }
+
+    // The first time at or after `timestamp` at which every bus leaves its
+    // offset in minutes later.
+    fn pattern_after(&self, timestamp: &BigInt) -> Result<BigInt> {
+        let (residue, period) = solve_offsets(&self.buses)?;
+        Ok(timestamp + (residue - timestamp).mod_floor(&period))
+    }
+}
+
+fn next_departure(timestamp: usize, timetable: &Timetable) -> Result<usize> {
+    let (time, bus_id) = *timetable
+        .departures_after(timestamp, 1)
+        .first()
+        .context("No buses in service.")?;
+    let time_to_wait = time - timestamp;
     println!("Bus id {} time to wait {}", bus_id, time_to_wait);
     Ok(bus_id * time_to_wait)
 }
 
 fn time_to_wait(timestamp: usize, bus_id: usize) -> usize {
-    (timestamp as f32 / bus_id as f32).ceil() as usize * bus_id - timestamp
+    (bus_id - timestamp % bus_id) % bus_id
 }
 
-fn golden_coin(bus_ids: &[&str]) -> usize {
-    let (merged_offset, merged_id) = bus_ids
-        .iter()
-        .enumerate()
-        .filter(|(_, raw)| raw != &&"x")
-        .map(|(offset, id)| {
-            (offset, id.parse::<usize>().expect("Must be a number."))
-        })
-        .fold((0, 1), |(acc_offset, acc_id), (offset, id)| {
-            let lcm = num::integer::lcm(acc_id, id);
-            let first = first_match((acc_offset, acc_id), (offset, id));
-            (first, lcm)
-        });
-    merged_id - merged_offset
+fn golden_coin(bus_ids: &[&str]) -> Result<BigInt> {
+    Timetable::parse(bus_ids)?.pattern_after(&BigInt::zero())
 }
 
-fn first_match(
-    (offset_a, id_a): (usize, usize),
-    (offset_b, id_b): (usize, usize),
-) -> usize {
-    let mut multiplier = 0;
-    loop {
-        multiplier += 1;
-        let candidate = id_a * multiplier + offset_a;
-        if (candidate > offset_b) && (candidate - offset_b) % id_b == 0 {
-            return candidate;
-        }
+// Every timestamp at which each bus departs `offset` minutes after it, as
+// the smallest one and the period at which the pattern repeats.
+fn solve_offsets(buses: &[(usize, usize)]) -> Result<(BigInt, BigInt)> {
+    buses.iter().try_fold(
+        (BigInt::zero(), BigInt::from(1)),
+        |(residue, modulus), &(offset, id)| {
+            let id = BigInt::from(id);
+            let wanted = (-BigInt::from(offset)).mod_floor(&id);
+            crt((&residue, &modulus), (&wanted, &id)).with_context(|| {
+                format!(
+                    "Bus {} can never leave {} minutes after the ones \
+                     before it.",
+                    id, offset
+                )
+            })
+        },
+    )
+}
+
+// Merges x = a1 (mod n1) and x = a2 (mod n2) into a single congruence mod
+// lcm(n1, n2). The moduli don't need to be coprime, but then the residues
+// have to agree on their common factor.
+fn crt(
+    (a1, n1): (&BigInt, &BigInt),
+    (a2, n2): (&BigInt, &BigInt),
+) -> Result<(BigInt, BigInt)> {
+    let ExtendedGcd { gcd, x, .. } = n1.extended_gcd(n2);
+    let diff = a2 - a1;
+    if !diff.is_multiple_of(&gcd) {
+        bail!(
+            "{} mod {} and {} mod {} are inconsistent, they differ modulo {}.",
+            a1,
+            n1,
+            a2,
+            n2,
+            gcd
+        );
     }
+    let lcm = n1 / &gcd * n2;
+    // x is the inverse of n1 / gcd modulo n2 / gcd.
+    let step = (diff / &gcd * x).mod_floor(&(n2 / &gcd));
+    let residue = (a1 + n1 * step).mod_floor(&lcm);
+    Ok((residue, lcm))
 }
 
 #[cfg(test)]
@@ -70,8 +197,73 @@ mod test {
 
     #[test]
     fn examples() {
-        assert_eq!(golden_coin(&["x", "x", "3", "x", "5"]), 1);
-        assert_eq!(golden_coin(&["x", "x", "3", "7", "5"]), 46);
-        assert_eq!(golden_coin(&["17", "x", "13", "19"]), 3417);
+        let coin = |ids: &[&str]| golden_coin(ids).unwrap();
+        assert_eq!(coin(&["x", "x", "3", "x", "5"]), BigInt::from(1));
+        assert_eq!(coin(&["x", "x", "3", "7", "5"]), BigInt::from(46));
+        assert_eq!(coin(&["17", "x", "13", "19"]), BigInt::from(3417));
+        assert_eq!(
+            coin(&["1789", "37", "47", "1889"]),
+            BigInt::from(1202161486)
+        );
+    }
+
+    #[test]
+    fn shared_factors() {
+        assert_eq!(golden_coin(&["4", "x", "6"]).unwrap(), BigInt::from(4));
+        assert!(golden_coin(&["4", "6"]).is_err());
+    }
+
+    const EXAMPLE: [&str; 8] = ["7", "13", "x", "x", "59", "x", "31", "19"];
+
+    #[test]
+    fn departures() {
+        let timetable = Timetable::parse(&EXAMPLE).unwrap();
+        assert_eq!(next_departure(939, &timetable).unwrap(), 295);
+        assert_eq!(
+            timetable.departures_after(939, 3),
+            vec![(944, 59), (945, 7), (949, 13)]
+        );
+    }
+
+    #[test]
+    fn windows() {
+        let timetable = Timetable::parse(&EXAMPLE).unwrap();
+        assert_eq!(timetable.first_window(&[7, 13], 0, 0).unwrap(), 0);
+        assert_eq!(timetable.first_window(&[7, 13], 0, 1).unwrap(), 91);
+        assert_eq!(timetable.first_window(&[7, 13], 1, 1).unwrap(), 13);
+        // Buses always meet at the lcm of their ids.
+        assert_eq!(timetable.first_window(&[59, 31], 0, 1).unwrap(), 1829);
+        // At 4 bus 5 leaves in 1 and bus 7 in 3, so the window opens
+        // between departures.
+        let timetable = Timetable::parse(&["5", "7"]).unwrap();
+        assert_eq!(timetable.first_window(&[5, 7], 3, 1).unwrap(), 4);
+        assert_eq!(timetable.first_window(&[5, 7], 3, 5).unwrap(), 5);
+        assert_eq!(timetable.first_window(&[5, 7], 1, 1).unwrap(), 14);
+    }
+
+    #[test]
+    fn window_errors() {
+        let timetable = Timetable::parse(&EXAMPLE).unwrap();
+        assert!(timetable.first_window(&[], 1, 1).is_err());
+        // Bus 5 isn't in the timetable.
+        assert!(timetable.first_window(&[7, 5], 1, 1).is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        let timetable = Timetable::parse(&EXAMPLE).unwrap();
+        let first = BigInt::from(1068781);
+        assert_eq!(timetable.pattern_after(&BigInt::zero()).unwrap(), first);
+        assert_eq!(timetable.pattern_after(&first).unwrap(), first);
+        assert_eq!(
+            timetable.pattern_after(&(first + 1)).unwrap(),
+            BigInt::from(1068781 + 7 * 13 * 59 * 31 * 19)
+        );
+    }
+
+    #[test]
+    fn waiting() {
+        assert_eq!(time_to_wait(939, 59), 5);
+        assert_eq!(time_to_wait(944, 59), 0);
     }
 }
@@ -2,159 +2,292 @@ use anyhow::{bail, Context, Error, Result};
 use aoc_2020::read_entries;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::{
-    collections::HashMap,
-    str::{self, FromStr},
-    usize,
-};
+use std::{collections::HashMap, str::FromStr};
 
 lazy_static! {
     static ref MEM_REGEX: Regex =
         Regex::new("^mem\\[(?P<address>\\d+)\\] = (?P<value>\\d+)$").unwrap();
 }
 
-struct ProgramV1 {
-    mask: Vec<(usize, u8)>,
-    memory: HashMap<usize, usize>,
+const DEFAULT_WIDTH: u32 = 36;
+
+fn check_width(width: u32) -> Result<()> {
+    if width == 0 || width > 64 {
+        bail!("Bus width must be between 1 and 64 bits, not {}.", width);
+    }
+    Ok(())
 }
 
-impl ProgramV1 {
-    fn new() -> Self {
-        ProgramV1 {
-            mask: vec![],
-            memory: HashMap::new(),
+fn check_fits(value: u64, width: u32, what: &str) -> Result<()> {
+    if width < 64 && value >> width != 0 {
+        bail!("{} {} doesn't fit on a {} bit bus.", what, value, width);
+    }
+    Ok(())
+}
+
+// A mask held as bitmasks rather than text: `ones` and `zeros` are the bits
+// the mask forces and `floating` the ones marked X.
+#[derive(Clone, Copy, Default)]
+struct Mask {
+    width: u32,
+    ones: u64,
+    zeros: u64,
+    floating: u64,
+}
+
+impl Mask {
+    fn parse(input: &str, width: u32) -> Result<Self> {
+        check_width(width)?;
+        if input.len() != width as usize {
+            bail!(
+                "Mask {} is {} bits long, the bus is {} bits wide.",
+                input,
+                input.len(),
+                width
+            );
+        }
+        let mut mask = Mask {
+            width,
+            ..Mask::default()
+        };
+        for c in input.chars() {
+            mask.ones <<= 1;
+            mask.zeros <<= 1;
+            mask.floating <<= 1;
+            match c {
+                '1' => mask.ones |= 1,
+                '0' => mask.zeros |= 1,
+                'X' => mask.floating |= 1,
+                hm => bail!("What is {} in a mask?", hm),
+            }
         }
+        Ok(mask)
     }
 
-    fn execute(&mut self, command: &Command) -> Result<()> {
-        match command {
-            Command::Mask(mask) => Ok(self.update_mask(&mask)),
-            Command::Mem(address, value) => self.set_mem(*address, *value),
+    fn apply(&self, val: u64) -> u64 {
+        val & !self.zeros | self.ones
+    }
+
+    fn decode(&self, address: u64) -> AddressPattern {
+        AddressPattern {
+            bits: (address | self.ones) & !self.floating,
+            floating: self.floating,
         }
     }
+}
 
-    fn update_mask(&mut self, mask_str: &str) {
-        self.mask = mask_str
-            .as_bytes()
-            .iter()
-            .cloned()
-            .enumerate()
-            .filter(|(_, c)| c != &b'X')
-            .collect();
+// A set of addresses: every address that matches `bits` outside of the
+// `floating` bits, which can take any value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AddressPattern {
+    bits: u64,
+    floating: u64,
+}
+
+impl AddressPattern {
+    fn len(&self) -> u128 {
+        1 << self.floating.count_ones()
     }
 
-    fn set_mem(&mut self, address: usize, val: usize) -> Result<()> {
-        let mask_val = self.apply_mask(val)?;
-        self.memory.insert(address, mask_val);
-        Ok(())
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        let fixed = !self.floating & !other.floating;
+        if (self.bits ^ other.bits) & fixed != 0 {
+            return None;
+        }
+        let floating = self.floating & other.floating;
+        Some(Self {
+            bits: (self.bits | other.bits) & !floating,
+            floating,
+        })
     }
 
-    fn apply_mask(&self, val: usize) -> Result<usize> {
-        let mut bin = format!("{:036b}", val).into_bytes();
-        for (offset, val) in &self.mask {
-            bin[*offset] = *val;
+    // The addresses in `self` but not in `other`, as disjoint patterns. Each
+    // bit that floats here but is fixed in `other` splits off the addresses
+    // that disagree with `other` on it.
+    fn subtract(&self, other: &Self) -> Vec<Self> {
+        let common = match self.intersection(other) {
+            Some(common) => common,
+            None => return vec![*self],
+        };
+        let mut pieces = vec![];
+        let mut rest = *self;
+        let mut splits = self.floating & !other.floating;
+        while splits != 0 {
+            let bit = splits & splits.wrapping_neg();
+            splits &= !bit;
+            rest.floating &= !bit;
+            pieces.push(Self {
+                bits: rest.bits | (!common.bits & bit),
+                floating: rest.floating,
+            });
+            rest.bits |= common.bits & bit;
         }
-        usize::from_str_radix(
-            str::from_utf8(&bin).context("Failed to parse what I created.")?,
-            2,
-        )
-        .context("Unparseable binary.")
+        pieces
     }
 
-    fn mem_sum(&self) -> usize {
-        self.memory.values().sum()
+    // Every address in the pattern, each exactly once, by walking the
+    // subsets of the floating bits.
+    fn addresses(&self) -> FloatingAddresses {
+        FloatingAddresses {
+            base: self.bits,
+            floating: self.floating,
+            subset: Some(0),
+        }
     }
 }
 
-struct ProgramV2 {
-    mask: Vec<u8>,
-    memory: HashMap<usize, usize>,
+struct FloatingAddresses {
+    base: u64,
+    floating: u64,
+    subset: Option<u64>,
 }
 
-impl ProgramV2 {
-    fn new() -> Self {
-        ProgramV2 {
-            mask: vec![],
+impl Iterator for FloatingAddresses {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let subset = self.subset?;
+        self.subset = if subset == self.floating {
+            None
+        } else {
+            Some(subset.wrapping_sub(self.floating) & self.floating)
+        };
+        Some(self.base | subset)
+    }
+}
+
+struct ProgramV1 {
+    mask: Mask,
+    memory: HashMap<u64, u64>,
+}
+
+impl ProgramV1 {
+    fn new(width: u32) -> Result<Self> {
+        check_width(width)?;
+        Ok(ProgramV1 {
+            mask: Mask {
+                width,
+                ..Mask::default()
+            },
             memory: HashMap::new(),
-        }
+        })
     }
 
     fn execute(&mut self, command: &Command) -> Result<()> {
+        command.check(self.mask.width)?;
         match command {
-            Command::Mask(mask) => self.mask = mask.clone().into_bytes(),
+            Command::Mask(mask) => self.mask = *mask,
             Command::Mem(address, value) => {
-                self.set_mem_range(*address, *value)?
+                self.memory.insert(*address, self.mask.apply(*value));
             }
         }
         Ok(())
     }
 
-    fn set_mem_range(&mut self, address: usize, val: usize) -> Result<()> {
-        for a in self.addresses(address)? {
-            self.memory.insert(a, val);
+    fn mem_sum(&self) -> u128 {
+        self.memory.values().map(|&v| v as u128).sum()
+    }
+}
+
+// Where version 2 keeps the values it writes.
+trait Memory {
+    fn write(&mut self, pattern: AddressPattern, value: u64);
+    fn sum(&self) -> u128;
+}
+
+// Writes every decoded address out, which is only feasible while masks
+// have few floating bits.
+#[derive(Default)]
+struct ExpandedMemory(HashMap<u64, u64>);
+
+impl Memory for ExpandedMemory {
+    fn write(&mut self, pattern: AddressPattern, value: u64) {
+        for address in pattern.addresses() {
+            self.0.insert(address, value);
         }
-        Ok(())
     }
 
-    fn addresses(&self, address: usize) -> Result<Vec<usize>> {
-        let mut address_bin = format!("{:036b}", address).into_bytes();
-        for (i, byte) in self.mask.iter().enumerate() {
-            match byte {
-                b'1' => address_bin[i] = b'1',
-                b'0' => {}
-                b'X' => address_bin[i] = b'0',
-                hm => bail!("What is {}", hm),
-            }
+    fn sum(&self) -> u128 {
+        self.0.values().map(|&v| v as u128).sum()
+    }
+}
+
+// Keeps each write as a pattern, carving later writes out of the earlier
+// patterns they overlap so that the stored patterns stay disjoint.
+#[derive(Default)]
+struct SymbolicMemory(Vec<(AddressPattern, u64)>);
+
+impl Memory for SymbolicMemory {
+    fn write(&mut self, pattern: AddressPattern, value: u64) {
+        let mut remaining = vec![];
+        for (existing, existing_value) in self.0.drain(..) {
+            remaining.extend(
+                existing
+                    .subtract(&pattern)
+                    .into_iter()
+                    .map(|piece| (piece, existing_value)),
+            );
         }
+        remaining.push((pattern, value));
+        self.0 = remaining;
+    }
 
-        let floating_indices: Vec<usize> = self
-            .mask
+    fn sum(&self) -> u128 {
+        self.0
             .iter()
-            .enumerate()
-            .filter(|(_, &c)| c == b'X')
-            .map(|(i, _)| i)
-            .collect();
-
-        let mut addresses = vec![];
-
-        for v in 0..2_usize.pow(floating_indices.len() as u32) {
-            let version_bin = format!("{:064b}", v).into_bytes();
-            for (index, value) in
-                floating_indices.iter().zip(version_bin.iter().rev())
-            {
-                address_bin[*index] = *value;
-                addresses.push(
-                    usize::from_str_radix(
-                        str::from_utf8(&address_bin)
-                            .context("Failed to parse what I created.")?,
-                        2,
-                    )
-                    .context("Unparseable binary.")?,
-                )
+            .map(|(pattern, value)| pattern.len() * *value as u128)
+            .sum()
+    }
+}
+
+struct ProgramV2<M: Memory> {
+    mask: Mask,
+    memory: M,
+}
+
+impl<M: Memory> ProgramV2<M> {
+    fn new(width: u32, memory: M) -> Result<Self> {
+        check_width(width)?;
+        Ok(ProgramV2 {
+            mask: Mask {
+                width,
+                ..Mask::default()
+            },
+            memory,
+        })
+    }
+
+    fn execute(&mut self, command: &Command) -> Result<()> {
+        command.check(self.mask.width)?;
+        match command {
+            Command::Mask(mask) => self.mask = *mask,
+            Command::Mem(address, value) => {
+                self.memory.write(self.mask.decode(*address), *value)
             }
         }
-        Ok(addresses)
+        Ok(())
     }
 
-    fn mem_sum(&self) -> usize {
-        self.memory.values().sum()
+    fn mem_sum(&self) -> u128 {
+        self.memory.sum()
     }
 }
 
 enum Command {
-    Mask(String),
-    Mem(usize, usize),
+    Mask(Mask),
+    Mem(u64, u64),
 }
 
-impl FromStr for Command {
-    type Err = Error;
-
-    fn from_str(input: &str) -> Result<Self> {
+impl Command {
+    // Parses a command for a bus `width` bits wide, rejecting masks of the
+    // wrong length and values that don't fit.
+    fn parse(input: &str, width: u32) -> Result<Self> {
         let mut parts = input.split(" = ");
-        Ok(match parts.next() {
-            Some("mask") => Command::Mask(
-                parts.next().context("Missing mask string.")?.to_string(),
-            ),
+        let command = match parts.next() {
+            Some("mask") => Command::Mask(Mask::parse(
+                parts.next().context("Missing mask string.")?,
+                width,
+            )?),
             Some(memory) if memory.starts_with("mem") => {
                 match MEM_REGEX.captures(input) {
                     Some(captures) => Command::Mem(
@@ -175,22 +308,177 @@ impl FromStr for Command {
                 }
             }
             _ => bail!("Missing command part."),
-        })
+        };
+        command.check(width)?;
+        Ok(command)
+    }
+
+    fn check(&self, width: u32) -> Result<()> {
+        match self {
+            Command::Mask(mask) if mask.width != width => bail!(
+                "Mask is {} bits wide, the bus is {} bits wide.",
+                mask.width,
+                width
+            ),
+            Command::Mask(_) => Ok(()),
+            Command::Mem(address, value) => {
+                check_fits(*address, width, "Address")?;
+                check_fits(*value, width, "Value")
+            }
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Self::parse(input, DEFAULT_WIDTH)
     }
 }
 
 fn main() -> Result<()> {
     let commands: Vec<Command> =
         read_entries::<Command>("./data/day-14.txt").collect();
-    let mut program = ProgramV1::new();
+    let mut program = ProgramV1::new(DEFAULT_WIDTH)?;
     for command in &commands {
         program.execute(command)?;
     }
     println!("Mem sum = {}", program.mem_sum());
-    let mut program = ProgramV2::new();
-    for command in &commands {
+    let sum = if std::env::args().any(|arg| arg == "--expanded") {
+        run_v2(&commands, DEFAULT_WIDTH, ExpandedMemory::default())?
+    } else {
+        run_v2(&commands, DEFAULT_WIDTH, SymbolicMemory::default())?
+    };
+    println!("Mem sum = {}", sum);
+    Ok(())
+}
+
+fn run_v2(
+    commands: &[Command],
+    width: u32,
+    memory: impl Memory,
+) -> Result<u128> {
+    let mut program = ProgramV2::new(width, memory)?;
+    for command in commands {
         program.execute(command)?;
     }
-    println!("Mem sum = {}", program.mem_sum());
-    Ok(())
+    Ok(program.mem_sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(input: &str) -> Vec<Command> {
+        input.lines().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn example_v1() {
+        let mut program = ProgramV1::new(DEFAULT_WIDTH).unwrap();
+        for command in &commands(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X
+mem[8] = 11
+mem[7] = 101
+mem[8] = 0",
+        ) {
+            program.execute(command).unwrap();
+        }
+        assert_eq!(165, program.mem_sum());
+    }
+
+    const EXAMPLE_V2: &str = "mask = 000000000000000000000000000000X1001X
+mem[42] = 100
+mask = 00000000000000000000000000000000X0XX
+mem[26] = 1";
+
+    #[test]
+    fn example_v2() {
+        let commands = commands(EXAMPLE_V2);
+        assert_eq!(
+            208,
+            run_v2(&commands, 36, ExpandedMemory::default()).unwrap()
+        );
+        assert_eq!(
+            208,
+            run_v2(&commands, 36, SymbolicMemory::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn floating_addresses() {
+        let mask =
+            Mask::parse("000000000000000000000000000000X1001X", 36).unwrap();
+        let mut addresses: Vec<_> = mask.decode(42).addresses().collect();
+        addresses.sort_unstable();
+        assert_eq!(vec![26, 27, 58, 59], addresses);
+    }
+
+    #[test]
+    fn pattern_subtraction() {
+        let a = AddressPattern {
+            bits: 0b0000,
+            floating: 0b1111,
+        };
+        let b = AddressPattern {
+            bits: 0b0101,
+            floating: 0b0010,
+        };
+        let pieces = a.subtract(&b);
+        assert_eq!(14, pieces.iter().map(AddressPattern::len).sum::<u128>());
+        for (i, piece) in pieces.iter().enumerate() {
+            assert_eq!(None, piece.intersection(&b));
+            for other in &pieces[i + 1..] {
+                assert_eq!(None, piece.intersection(other));
+            }
+        }
+    }
+
+    #[test]
+    fn huge_floating_masks() {
+        let commands = commands(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 2
+mask = 0XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 1",
+        );
+        let total = 1_u128 << 36;
+        assert_eq!(
+            total + total / 2,
+            run_v2(&commands, 36, SymbolicMemory::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn bus_width() {
+        assert!(Command::parse("mask = 1X0", 3).is_ok());
+        assert!(Command::parse("mask = 1X0", 4).is_err());
+        assert!(Command::parse("mask = 1Y0", 3).is_err());
+        assert!(Command::parse("mem[7] = 7", 3).is_ok());
+        assert!(Command::parse("mem[7] = 8", 3).is_err());
+        assert!(Command::parse("mem[8] = 7", 3).is_err());
+        assert!(Command::parse(&format!("mem[0] = {}", u64::MAX), 64).is_ok());
+        assert!(Command::parse("mask = 1", 65).is_err());
+
+        let mask = Command::parse("mask = 1X0", 3).unwrap();
+        let mut program = ProgramV1::new(DEFAULT_WIDTH).unwrap();
+        assert!(program.execute(&mask).is_err());
+    }
+
+    #[test]
+    fn wide_sums() {
+        let mut program = ProgramV1::new(64).unwrap();
+        let commands = [
+            format!("mask = {}", "X".repeat(64)),
+            format!("mem[0] = {}", u64::MAX),
+            format!("mem[1] = {}", u64::MAX),
+        ];
+        for command in &commands {
+            program
+                .execute(&Command::parse(command, 64).unwrap())
+                .unwrap();
+        }
+        assert_eq!(program.mem_sum(), 2 * u64::MAX as u128);
+    }
 }
@@ -1,66 +1,163 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, convert::TryFrom};
 
-struct State {
-    latest: usize,
-    prev: usize,
+// The memory game as a stream of spoken numbers. The turn each number was
+// last spoken on lives in a dense table for numbers below the expected turn
+// count (no number spoken can be larger than the turn), anything larger
+// falls back to a map. Turns are 1 based so 0 in the table means unseen.
+struct Game {
+    starting: Vec<usize>,
+    last_seen: Vec<u32>,
+    overflow: HashMap<usize, usize>,
+    turn: usize,
+    next: usize,
+    distinct: usize,
+    zeros: usize,
 }
 
-impl State {
-    fn new(turn: usize) -> Self {
+impl Game {
+    fn new(starting_nums: &[usize], turns: usize) -> Self {
+        let table_size = if u32::try_from(turns).is_ok() {
+            turns
+        } else {
+            0
+        };
         Self {
-            latest: turn,
-            prev: turn,
+            starting: starting_nums.to_vec(),
+            last_seen: vec![0; table_size],
+            overflow: HashMap::new(),
+            turn: 0,
+            next: 0,
+            distinct: 0,
+            zeros: 0,
         }
     }
 
-    fn updated(&self, turn: usize) -> Self {
-        Self {
-            latest: turn,
-            prev: self.latest,
+    // Records that `num` was spoken this turn, returning the turn it was
+    // last spoken on before.
+    fn remember(&mut self, num: usize) -> Option<usize> {
+        if num < self.last_seen.len() {
+            if let Ok(turn) = u32::try_from(self.turn) {
+                let prev = std::mem::replace(&mut self.last_seen[num], turn);
+                return if prev == 0 { None } else { Some(prev as usize) };
+            }
+            self.spill();
         }
+        self.overflow.insert(num, self.turn)
     }
-}
 
-struct Game {
-    numbers: HashMap<usize, State>,
-    turn: usize,
-    last: usize,
-}
+    // How many different numbers have been spoken so far.
+    fn distinct(&self) -> usize {
+        self.distinct
+    }
 
-impl Game {
-    fn new() -> Self {
-        Self {
-            numbers: HashMap::new(),
-            turn: 0,
-            last: 0,
+    // How many of the numbers spoken so far were zeros.
+    fn zeros(&self) -> usize {
+        self.zeros
+    }
+
+    // The share of turns so far that were zeros, if there have been any.
+    fn zero_frequency(&self) -> Option<f64> {
+        if self.turn == 0 {
+            None
+        } else {
+            Some(self.zeros as f64 / self.turn as f64)
         }
     }
 
-    fn feed(&mut self, num: usize) -> usize {
+    // Moves the table into the map once turns no longer fit into it.
+    fn spill(&mut self) {
+        for (num, turn) in self.last_seen.drain(..).enumerate() {
+            if turn != 0 {
+                self.overflow.insert(num, turn as usize);
+            }
+        }
+    }
+}
+
+impl Iterator for Game {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
         self.turn += 1;
-        self.last = num;
-        let entry = self.numbers.entry(num).or_insert(State::new(self.turn));
-        *entry = entry.updated(self.turn);
-        entry.latest - entry.prev
+        let spoken = match self.starting.get(self.turn - 1) {
+            Some(&num) => num,
+            None => self.next,
+        };
+        self.next = match self.remember(spoken) {
+            Some(prev) => self.turn - prev,
+            None => {
+                self.distinct += 1;
+                0
+            }
+        };
+        if spoken == 0 {
+            self.zeros += 1;
+        }
+        Some(spoken)
     }
 }
 
+// The number spoken on the given (1 based) turn.
+fn value_at(starting_nums: &[usize], turn: usize) -> Option<usize> {
+    Game::new(starting_nums, turn).nth(turn.checked_sub(1)?)
+}
+
 fn run_game(starting_nums: &[usize], ending_turn: usize) -> usize {
-    let mut game = Game::new();
-    let mut next = 0;
-    for num in starting_nums {
-        next = game.feed(*num);
+    value_at(starting_nums, ending_turn).expect("There is at least one turn.")
+}
+
+// A game played up to some turn, with what was seen on the way.
+struct Playthrough {
+    game: Game,
+    // The number spoken on the last turn.
+    last: Option<usize>,
+    // The first turn the watched number was spoken on.
+    first_turn: Option<usize>,
+}
+
+// Plays the game up to the given turn in a single pass, watching for the
+// first time `watched` is spoken.
+fn play(starting_nums: &[usize], turns: usize, watched: usize) -> Playthrough {
+    let mut game = Game::new(starting_nums, turns);
+    let mut last = None;
+    let mut first_turn = None;
+    for (i, spoken) in game.by_ref().take(turns).enumerate() {
+        if spoken == watched && first_turn.is_none() {
+            first_turn = Some(i + 1);
+        }
+        last = Some(spoken);
     }
-    while game.turn < ending_turn {
-        next = game.feed(next);
+    Playthrough {
+        game,
+        last,
+        first_turn,
     }
-    game.last
 }
 
 fn main() {
     let starting_nums = [7, 14, 0, 17, 11, 1, 2];
     println!("Number is {}", run_game(&starting_nums, 2020));
-    println!("Number is {}", run_game(&starting_nums, 30_000_000));
+
+    let turns = 30_000_000;
+    let Playthrough {
+        game,
+        last,
+        first_turn,
+    } = play(&starting_nums, turns, 2020);
+    println!("Number is {}", last.expect("There is at least one turn."));
+    println!("{} distinct numbers by turn {}", game.distinct(), turns);
+    if let Some(frequency) = game.zero_frequency() {
+        println!(
+            "{} zeros, {:.4} of the first {} turns",
+            game.zeros(),
+            frequency,
+            turns
+        );
+    }
+    match first_turn {
+        Some(turn) => println!("2020 is first spoken on turn {}", turn),
+        None => println!("2020 isn't spoken in {} turns", turns),
+    }
 }
 
 #[cfg(test)]
@@ -72,4 +169,47 @@ mod test {
         assert_eq!(1, run_game(&[1, 3, 2], 2020));
         assert_eq!(10, run_game(&[2, 1, 3], 2020));
     }
+
+    #[test]
+    fn sequence() {
+        assert_eq!(
+            vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0],
+            Game::new(&[0, 3, 6], 10).take(10).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(4), value_at(&[0, 3, 6], 9));
+        assert_eq!(None, value_at(&[0, 3, 6], 0));
+    }
+
+    #[test]
+    fn analysis() {
+        // Turns 1 to 10 of the example are 0, 3, 6, 0, 3, 3, 1, 0, 4, 0.
+        assert_eq!(3, play(&[0, 3, 6], 3, 0).game.distinct());
+        let played = play(&[0, 3, 6], 10, 0);
+        assert_eq!(5, played.game.distinct());
+        assert_eq!(4, played.game.zeros());
+        assert!((played.game.zero_frequency().unwrap() - 0.4).abs() < 1e-9);
+        assert_eq!(Some(0), played.last);
+
+        let first_turn = |num, turns| play(&[0, 3, 6], turns, num).first_turn;
+        assert_eq!(Some(1), first_turn(0, 10));
+        assert_eq!(Some(7), first_turn(1, 10));
+        assert_eq!(Some(9), first_turn(4, 10));
+        assert_eq!(None, first_turn(4, 8));
+
+        let unplayed = play(&[0, 3, 6], 0, 0);
+        assert_eq!(None, unplayed.game.zero_frequency());
+        assert_eq!(None, unplayed.last);
+    }
+
+    #[test]
+    fn small_table() {
+        // Everything spoken past the table size goes through the map.
+        let mut game = Game::new(&[0, 3, 6], 3);
+        assert_eq!(Some(436), game.nth(2019));
+    }
+
+    #[test]
+    fn long_game() {
+        assert_eq!(175594, run_game(&[0, 3, 6], 30_000_000));
+    }
 }
@@ -1,27 +1,112 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Error, Result};
+use aoc_2020::flag_value;
 use std::{
-    collections::HashSet, fs::read_to_string, ops::RangeInclusive, str::FromStr,
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::read_to_string,
+    ops::RangeInclusive,
+    str::FromStr,
 };
 
 type Ticket = Vec<usize>;
 
-struct Rule {
-    name: String,
+// Sorted, disjoint and non-touching inclusive ranges, so that membership is
+// a binary search and two sets print the same if they hold the same values.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct IntervalSet {
     ranges: Vec<RangeInclusive<usize>>,
 }
 
-impl FromStr for Rule {
+impl IntervalSet {
+    fn new(mut ranges: Vec<RangeInclusive<usize>>) -> Self {
+        ranges.retain(|range| !range.is_empty());
+        ranges.sort_by_key(|range| *range.start());
+        let mut merged: Vec<RangeInclusive<usize>> = vec![];
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last)
+                    if *range.start() <= last.end().saturating_add(1) =>
+                {
+                    if range.end() > last.end() {
+                        *last = *last.start()..=*range.end();
+                    }
+                }
+                _ => merged.push(range),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    fn contains(&self, num: &usize) -> bool {
+        self.ranges
+            .binary_search_by(|range| {
+                if range.end() < num {
+                    Ordering::Less
+                } else if range.start() > num {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self::new(self.ranges.iter().chain(&other.ranges).cloned().collect())
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        let mut ranges = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (&self.ranges[i], &other.ranges[j]);
+            let start = *a.start().max(b.start());
+            let end = *a.end().min(b.end());
+            if start <= end {
+                ranges.push(start..=end);
+            }
+            if a.end() < b.end() {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Self { ranges }
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    // How far `num` is from the closest range, 0 if it's in one.
+    fn distance(&self, num: usize) -> usize {
+        self.ranges
+            .iter()
+            .map(|range| {
+                if num < *range.start() {
+                    range.start() - num
+                } else {
+                    num.saturating_sub(*range.end())
+                }
+            })
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+}
+
+impl FromStr for IntervalSet {
     type Err = Error;
 
     fn from_str(input: &str) -> Result<Self> {
-        let mut parts = input.split(": ");
-        let name = parts.next().context("Missing name section.")?.to_string();
-        let ranges = parts
-            .next()
-            .context("Missing range section.")?
+        let ranges = input
             .split(" or ")
             .map(|range_str| {
-                let mut parts = range_str.split("-");
+                let mut parts = range_str.split('-');
                 let low = parts
                     .next()
                     .context("Missing low.")?
@@ -35,17 +120,93 @@ impl FromStr for Rule {
                 Ok(low..=high)
             })
             .collect::<Result<_>>()?;
+        Ok(Self::new(ranges))
+    }
+}
+
+impl Display for IntervalSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|range| format!("{}-{}", range.start(), range.end()))
+            .collect();
+        write!(f, "{}", ranges.join(" or "))
+    }
+}
+
+struct Rule {
+    name: String,
+    ranges: IntervalSet,
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut parts = input.split(": ");
+        let name = parts.next().context("Missing name section.")?.to_string();
+        let ranges = parts.next().context("Missing range section.")?.parse()?;
         Ok(Self { name, ranges })
     }
 }
 
+impl Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.ranges)
+    }
+}
+
 impl Rule {
     fn contains(&self, num: &usize) -> bool {
-        self.ranges.iter().any(|range| range.contains(num))
+        self.ranges.contains(num)
+    }
+
+    fn distance(&self, num: usize) -> usize {
+        self.ranges.distance(num)
+    }
+
+    fn overlaps(&self, other: &Rule) -> bool {
+        self.ranges.overlaps(&other.ranges)
     }
 }
 
+// Every value accepted by at least one of the rules.
+fn combined(rules: &[Rule]) -> IntervalSet {
+    rules
+        .iter()
+        .fold(IntervalSet::default(), |acc, rule| acc.union(&rule.ranges))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ReportFormat {
+    Table,
+    Json,
+}
+
+// The report asked for with `--report [table|json] [--near N]`, as its
+// format and near miss distance.
+fn report_options(args: &[String]) -> Result<Option<(ReportFormat, usize)>> {
+    let format = match flag_value(args, "--report") {
+        Some(Some("table")) | Some(None) => ReportFormat::Table,
+        Some(Some("json")) => ReportFormat::Json,
+        Some(Some(hm)) => bail!("What is a {} report?", hm),
+        None => return Ok(None),
+    };
+    let near = flag_value(args, "--near")
+        .map(|near| {
+            near.context("--near needs a distance.")?
+                .parse()
+                .context("Unparseable near miss distance.")
+        })
+        .transpose()?
+        .unwrap_or(5);
+    Ok(Some((format, near)))
+}
+
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+
     let input = read_to_string("./data/day-16.txt")?;
     let (rules, ticket, mut scanned_tickets) = parse_input(&input)?;
 
@@ -54,20 +215,40 @@ fn main() -> Result<()> {
         get_scanning_error_rate(&rules, &scanned_tickets)
     );
 
+    if args.iter().any(|arg| arg == "--rules") {
+        println!("Any rule: {}", combined(&rules));
+        for rule in &rules {
+            let overlapping: Vec<&str> = rules
+                .iter()
+                .filter(|other| other.name != rule.name && rule.overlaps(other))
+                .map(|other| other.name.as_str())
+                .collect();
+            println!("{} (overlaps {})", rule, overlapping.join(", "));
+        }
+    }
+
+    if let Some((format, near)) = report_options(&args)? {
+        let report = scan(&rules, &scanned_tickets, near);
+        match format {
+            ReportFormat::Table => print!("{}", report.to_table()),
+            ReportFormat::Json => println!("{}", report.to_json()),
+        }
+    }
+
+    let valid = combined(&rules);
     let valid_scanned_tickets = scanned_tickets
         .drain(..)
-        .filter(|t| is_valid(&rules, &t))
+        .filter(|t| is_valid(&valid, t))
         .collect::<Vec<_>>();
 
-    let columns = identify_columns(&rules, &valid_scanned_tickets);
+    let columns = identify_columns(&rules, &valid_scanned_tickets)?;
 
     println!(
         "Departure product {}",
         columns
             .iter()
-            .enumerate()
-            .filter(|(_, c)| c.starts_with("departure"))
-            .map(|(i, _)| ticket[i])
+            .filter(|(name, _)| name.starts_with("departure"))
+            .map(|(_, &i)| ticket[i])
             .product::<usize>()
     );
     Ok(())
@@ -87,8 +268,7 @@ fn parse_input(input: &str) -> Result<(Vec<Rule>, Ticket, Vec<Ticket>)> {
             .next()
             .context("Missing ticket section.")?
             .split("\n")
-            .skip(1)
-            .next()
+            .nth(1)
             .context("Input ended before my ticket.")?,
     )?;
 
@@ -97,7 +277,7 @@ fn parse_input(input: &str) -> Result<(Vec<Rule>, Ticket, Vec<Ticket>)> {
         .context("Missing scanned ticket section.")?
         .split("\n")
         .skip(1)
-        .take_while(|&s| s != "")
+        .take_while(|&s| !s.is_empty())
         .map(parse_ticket)
         .collect::<Result<_>>()?;
     Ok((rules, ticket, scanned_tickets))
@@ -111,54 +291,452 @@ fn parse_ticket(input: &str) -> Result<Ticket> {
 }
 
 fn get_scanning_error_rate(rules: &[Rule], tickets: &[Ticket]) -> usize {
+    let valid = combined(rules);
     tickets
+        .iter()
+        .flatten()
+        .filter(|num| !valid.contains(num))
+        .sum()
+}
+
+fn is_valid(valid: &IntervalSet, ticket: &[usize]) -> bool {
+    ticket.iter().all(|num| valid.contains(num))
+}
+
+struct ValueReport {
+    value: usize,
+    // Indices of the rules the value satisfies.
+    rules: Vec<usize>,
+}
+
+// An invalid value that is within the near miss distance of one of the
+// rules.
+struct NearMiss {
+    ticket: usize,
+    value: usize,
+    rule: usize,
+    distance: usize,
+}
+
+struct ScanReport<'a> {
+    rules: &'a [Rule],
+    tickets: Vec<Vec<ValueReport>>,
+    near_misses: Vec<NearMiss>,
+}
+
+fn scan<'a>(
+    rules: &'a [Rule],
+    tickets: &[Ticket],
+    near: usize,
+) -> ScanReport<'a> {
+    let tickets: Vec<Vec<ValueReport>> = tickets
         .iter()
         .map(|ticket| {
             ticket
                 .iter()
-                .filter(|num| rules.iter().all(|rule| !rule.contains(num)))
-                .sum::<usize>()
+                .map(|&value| ValueReport {
+                    value,
+                    rules: (0..rules.len())
+                        .filter(|&r| rules[r].contains(&value))
+                        .collect(),
+                })
+                .collect()
         })
-        .sum()
+        .collect();
+
+    let mut near_misses = vec![];
+    for (ticket, values) in tickets.iter().enumerate() {
+        for report in values.iter().filter(|v| v.rules.is_empty()) {
+            let closest = rules
+                .iter()
+                .enumerate()
+                .map(|(rule, r)| (r.distance(report.value), rule))
+                .min();
+            if let Some((distance, rule)) = closest {
+                if distance <= near {
+                    near_misses.push(NearMiss {
+                        ticket,
+                        value: report.value,
+                        rule,
+                        distance,
+                    });
+                }
+            }
+        }
+    }
+
+    ScanReport {
+        rules,
+        tickets,
+        near_misses,
+    }
 }
 
-fn is_valid(rules: &[Rule], ticket: &[usize]) -> bool {
-    ticket
-        .iter()
-        .all(|num| rules.iter().any(|rule| rule.contains(num)))
+impl<'a> ScanReport<'a> {
+    fn invalid_values(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.tickets
+            .iter()
+            .enumerate()
+            .flat_map(|(ticket, values)| {
+                values
+                    .iter()
+                    .filter(|v| v.rules.is_empty())
+                    .map(move |v| (ticket, v.value))
+            })
+    }
+
+    fn error_rate(&self) -> usize {
+        self.invalid_values().map(|(_, value)| value).sum()
+    }
+
+    fn rule_names(&self, rules: &[usize]) -> Vec<&str> {
+        rules.iter().map(|&r| self.rules[r].name.as_str()).collect()
+    }
+
+    fn to_table(&self) -> String {
+        let mut table = format!("{:>6} {:>6}  rules\n", "ticket", "value");
+        for (ticket, values) in self.tickets.iter().enumerate() {
+            for report in values {
+                let rules = if report.rules.is_empty() {
+                    "INVALID".to_string()
+                } else {
+                    self.rule_names(&report.rules).join(", ")
+                };
+                table.push_str(&format!(
+                    "{:>6} {:>6}  {}\n",
+                    ticket, report.value, rules
+                ));
+            }
+        }
+        table.push_str(&format!("\nError rate {}\n", self.error_rate()));
+        table.push_str(&format!("Near misses {}\n", self.near_misses.len()));
+        for miss in &self.near_misses {
+            table.push_str(&format!(
+                "{:>6} {:>6}  {} off {}\n",
+                miss.ticket,
+                miss.value,
+                miss.distance,
+                self.rules[miss.rule].name
+            ));
+        }
+        table
+    }
+
+    fn to_json(&self) -> String {
+        let tickets: Vec<String> = self
+            .tickets
+            .iter()
+            .enumerate()
+            .map(|(ticket, values)| {
+                let entries: Vec<String> = values
+                    .iter()
+                    .map(|report| {
+                        format!(
+                            "{{\"value\":{},\"rules\":[{}]}}",
+                            report.value,
+                            self.rule_names(&report.rules)
+                                .iter()
+                                .map(|name| json_string(name))
+                                .collect::<Vec<_>>()
+                                .join(",")
+                        )
+                    })
+                    .collect();
+                let invalid: Vec<String> = values
+                    .iter()
+                    .filter(|v| v.rules.is_empty())
+                    .map(|v| v.value.to_string())
+                    .collect();
+                format!(
+                    "{{\"ticket\":{},\"values\":[{}],\"invalid\":[{}]}}",
+                    ticket,
+                    entries.join(","),
+                    invalid.join(",")
+                )
+            })
+            .collect();
+        let near_misses: Vec<String> = self
+            .near_misses
+            .iter()
+            .map(|miss| {
+                format!(
+                    "{{\"ticket\":{},\"value\":{},\"rule\":{},\"distance\":{}}}",
+                    miss.ticket,
+                    miss.value,
+                    json_string(&self.rules[miss.rule].name),
+                    miss.distance
+                )
+            })
+            .collect();
+        format!(
+            "{{\"tickets\":[{}],\"near_misses\":[{}],\"error_rate\":{}}}",
+            tickets.join(","),
+            near_misses.join(","),
+            self.error_rate()
+        )
+    }
 }
 
-fn identify_columns<'a>(
-    rules: &'a Vec<Rule>,
-    tickets: &[Ticket],
-) -> Vec<&'a str> {
-    let width = rules.len();
-    let mut candidate_columns = vec![HashSet::new(); width];
-    let mut columns = vec![""; width];
-
-    // A candidate column is one whose rule validates all tickets in that
-    // column.
-    for i in 0..width {
-        for rule in rules {
-            if tickets.iter().all(|ticket| rule.contains(&ticket[i])) {
-                candidate_columns[i].insert(&rule.name);
+fn json_string(input: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in input.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
             }
+            c => escaped.push(c),
         }
     }
+    escaped.push('"');
+    escaped
+}
+
+// Assigns every rule a column as a bipartite matching between rules and the
+// columns whose values they all accept. Fails if no assignment exists or if
+// more than one does.
+fn identify_columns(
+    rules: &[Rule],
+    tickets: &[Ticket],
+) -> Result<HashMap<String, usize>> {
+    let width = tickets.first().map_or(rules.len(), |ticket| ticket.len());
+    if let Some(ticket) = tickets.iter().find(|t| t.len() != width) {
+        bail!("Ticket {:?} doesn't have {} values.", ticket, width);
+    }
+
+    let candidates: Vec<Vec<usize>> = rules
+        .iter()
+        .map(|rule| {
+            (0..width)
+                .filter(|&i| tickets.iter().all(|t| rule.contains(&t[i])))
+                .collect()
+        })
+        .collect();
 
-    // Trim the candidate columns by checking which ones can only have 1 value
-    // and excluding it from everywhere else.
-    while let Some((i, column)) = candidate_columns
-        .iter_mut()
+    let mut matching = Matching {
+        candidates: &candidates,
+        owners: vec![None; width],
+        forbidden: None,
+    };
+
+    for rule in 0..rules.len() {
+        let mut visited = vec![false; width];
+        if !matching.augment(rule, &mut visited) {
+            // Everything the search reached competes for the same too few
+            // columns.
+            let columns: Vec<usize> =
+                (0..width).filter(|&c| visited[c]).collect();
+            let mut fields: Vec<&str> = columns
+                .iter()
+                .filter_map(|&c| matching.owners[c])
+                .chain(std::iter::once(rule))
+                .map(|r| rules[r].name.as_str())
+                .collect();
+            fields.sort_unstable();
+            bail!(
+                "No assignment possible, fields {:?} only fit columns {:?}.",
+                fields,
+                columns
+            );
+        }
+    }
+
+    let assigned: Vec<(usize, usize)> = matching
+        .owners
+        .iter()
         .enumerate()
-        .find(|(_, c)| c.len() == 1)
-    {
-        let rule = column.drain().next().expect("There is certainly one.");
-        for i in 0..width {
-            candidate_columns[i].remove(rule);
+        .filter_map(|(column, owner)| owner.map(|rule| (rule, column)))
+        .collect();
+
+    // The assignment is unique if no rule can be moved off its column while
+    // every other rule still finds one.
+    for &(rule, column) in &assigned {
+        matching.owners[column] = None;
+        matching.forbidden = Some((rule, column));
+        let mut visited = vec![false; width];
+        if matching.augment(rule, &mut visited) {
+            let other = matching
+                .owners
+                .iter()
+                .position(|&owner| owner == Some(rule))
+                .expect("Rule was just assigned.");
+            bail!(
+                "Ambiguous assignment, {} could be column {} or {}.",
+                rules[rule].name,
+                column,
+                other
+            );
+        }
+        matching.owners[column] = Some(rule);
+        matching.forbidden = None;
+    }
+
+    Ok(assigned
+        .into_iter()
+        .map(|(rule, column)| (rules[rule].name.clone(), column))
+        .collect())
+}
+
+struct Matching<'a> {
+    candidates: &'a [Vec<usize>],
+    owners: Vec<Option<usize>>,
+    forbidden: Option<(usize, usize)>,
+}
+
+impl<'a> Matching<'a> {
+    // Finds a column for `rule`, moving rules that already have one along
+    // to another of their candidates if needed.
+    fn augment(&mut self, rule: usize, visited: &mut [bool]) -> bool {
+        for &column in &self.candidates[rule] {
+            if visited[column] || self.forbidden == Some((rule, column)) {
+                continue;
+            }
+            visited[column] = true;
+            let free = match self.owners[column] {
+                None => true,
+                Some(owner) => self.augment(owner, visited),
+            };
+            if free {
+                self.owners[column] = Some(rule);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "class: 0-1 or 4-19
+row: 0-5 or 8-19
+seat: 0-13 or 16-19
+
+your ticket:
+11,12,13
+
+nearby tickets:
+3,9,18
+15,1,5
+5,14,9
+";
+
+    const SCAN_EXAMPLE: &str = "class: 1-3 or 5-7
+row: 6-11 or 33-44
+seat: 13-40 or 45-50
+
+your ticket:
+7,1,14
+
+nearby tickets:
+7,3,47
+40,4,50
+55,2,20
+38,6,12
+";
+
+    #[test]
+    fn interval_sets() {
+        let a: IntervalSet = "5-7 or 1-3 or 4-4 or 10-12".parse().unwrap();
+        assert_eq!("1-7 or 10-12", a.to_string());
+        let b: IntervalSet = "6-10 or 20-30".parse().unwrap();
+        assert_eq!("1-12 or 20-30", a.union(&b).to_string());
+        assert_eq!("6-7 or 10-10", a.intersection(&b).to_string());
+        assert!(a.overlaps(&b));
+        assert!(!a.overlaps(&"13-19".parse().unwrap()));
+        for num in &[1, 4, 7, 10, 12] {
+            assert!(a.contains(num));
         }
-        columns[i] = rule;
+        for num in &[0, 8, 9, 13, 100] {
+            assert!(!a.contains(num));
+        }
+        assert_eq!(2, a.distance(14));
+
+        let rule: Rule = "class: 1-3 or 5-7".parse().unwrap();
+        assert_eq!("class: 1-3 or 5-7", rule.to_string());
     }
 
-    columns
+    #[test]
+    fn scan_report() {
+        let (rules, _, tickets) = parse_input(SCAN_EXAMPLE).unwrap();
+        let report = scan(&rules, &tickets, 2);
+        assert_eq!(71, report.error_rate());
+        assert_eq!(
+            vec![(1, 4), (2, 55), (3, 12)],
+            report.invalid_values().collect::<Vec<_>>()
+        );
+        assert_eq!(vec![0, 1], report.tickets[0][0].rules);
+        let misses: Vec<_> = report
+            .near_misses
+            .iter()
+            .map(|miss| (miss.value, rules[miss.rule].name.as_str()))
+            .collect();
+        assert_eq!(vec![(4, "class"), (12, "row")], misses);
+        assert!(report.to_json().ends_with("\"error_rate\":71}"));
+        assert_eq!("\"a\\\"b\"", json_string("a\"b"));
+    }
+
+    #[test]
+    fn columns() {
+        let (rules, _, tickets) = parse_input(EXAMPLE).unwrap();
+        let columns = identify_columns(&rules, &tickets).unwrap();
+        assert_eq!(Some(&0), columns.get("row"));
+        assert_eq!(Some(&1), columns.get("class"));
+        assert_eq!(Some(&2), columns.get("seat"));
+    }
+
+    #[test]
+    fn ambiguous_columns() {
+        let (rules, _, tickets) = parse_input(
+            &EXAMPLE.replace("seat: 0-13 or 16-19", "seat: 0-1 or 4-19"),
+        )
+        .unwrap();
+        let error = identify_columns(&rules, &tickets).unwrap_err();
+        assert!(error.to_string().starts_with("Ambiguous"));
+    }
+
+    #[test]
+    fn impossible_columns() {
+        let (rules, _, tickets) = parse_input(
+            &EXAMPLE.replace("seat: 0-13 or 16-19", "seat: 0-2 or 20-30"),
+        )
+        .unwrap();
+        let error = identify_columns(&rules, &tickets).unwrap_err();
+        assert!(error.to_string().starts_with("No assignment"));
+    }
+
+    #[test]
+    fn report_flags() {
+        let options = |args: &str| {
+            let args: Vec<String> =
+                args.split_whitespace().map(String::from).collect();
+            report_options(&args)
+        };
+        assert_eq!(options("day_16").unwrap(), None);
+        assert_eq!(
+            options("day_16 --report").unwrap(),
+            Some((ReportFormat::Table, 5))
+        );
+        assert_eq!(
+            options("day_16 --report json").unwrap(),
+            Some((ReportFormat::Json, 5))
+        );
+        assert_eq!(
+            options("day_16 --report --near 3").unwrap(),
+            Some((ReportFormat::Table, 3))
+        );
+        assert_eq!(
+            options("day_16 --report table --near 3").unwrap(),
+            Some((ReportFormat::Table, 3))
+        );
+        assert_eq!(
+            options("day_16 --near 3 --report json").unwrap(),
+            Some((ReportFormat::Json, 3))
+        );
+        assert!(options("day_16 --report xml").is_err());
+        assert!(options("day_16 --report --near").is_err());
+        assert!(options("day_16 --report --near --rules").is_err());
+    }
 }
@@ -1,127 +1,479 @@
-use aoc_2020::read_entries;
-use std::{collections::HashSet, hash::Hash};
+use anyhow::{bail, Context, Error, Result};
+use aoc_2020::{flag_value, read_entries};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{self, Display},
+    hash::Hash,
+    str::FromStr,
+};
 
-trait HasNeighbors: Sized + Clone + Eq + Hash {
-    fn neighbors(&self) -> HashSet<Self>;
-}
-
-type Cube = (isize, isize, isize);
-type Cubes = HashSet<Cube>;
-
-impl HasNeighbors for Cube {
-    fn neighbors(&self) -> Cubes {
-        let (x, y, z) = self;
-        let mut neighbors = Cubes::new();
-        for xn in x - 1..=x + 1 {
-            for yn in y - 1..=y + 1 {
-                for zn in z - 1..=z + 1 {
-                    if (xn, yn, zn) != (*x, *y, *z) {
-                        neighbors.insert((xn, yn, zn));
-                    }
-                }
-            }
+// A Life-like rule, the neighbour counts at which an inactive cube becomes
+// active and an active one stays active.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Rule {
+    born: BTreeSet<usize>,
+    survives: BTreeSet<usize>,
+}
+
+impl Rule {
+    fn next_state(&self, active: bool, count: usize) -> bool {
+        if active {
+            self.survives.contains(&count)
+        } else {
+            self.born.contains(&count)
         }
-        neighbors
     }
 }
 
-type HyperCube = (isize, isize, isize, isize);
-type HyperCubes = HashSet<HyperCube>;
+impl Default for Rule {
+    fn default() -> Self {
+        "B3/S23".parse().expect("The default rule is valid.")
+    }
+}
+
+// Parses the counts of one half of a rulestring. Every digit is a count on
+// its own, unless they're separated by commas, which allows the counts above
+// 9 there are in 3 or more dimensions.
+fn parse_counts(input: &str) -> Result<BTreeSet<usize>> {
+    if input.contains(',') {
+        input
+            .split(',')
+            .map(|count| {
+                count
+                    .parse()
+                    .with_context(|| format!("Unparseable count {}.", count))
+            })
+            .collect()
+    } else {
+        input
+            .chars()
+            .map(|c| {
+                c.to_digit(10)
+                    .map(|count| count as usize)
+                    .with_context(|| format!("Unparseable count {}.", c))
+            })
+            .collect()
+    }
+}
 
-impl HasNeighbors for HyperCube {
-    fn neighbors(&self) -> HyperCubes {
-        let (x, y, z, q) = self;
-        let mut neighbors = HyperCubes::new();
-        for xn in x - 1..=x + 1 {
-            for yn in y - 1..=y + 1 {
-                for zn in z - 1..=z + 1 {
-                    for qn in q - 1..=q + 1 {
-                        if (xn, yn, zn, qn) != (*x, *y, *z, *q) {
-                            neighbors.insert((xn, yn, zn, qn));
-                        }
-                    }
-                }
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut born = None;
+        let mut survives = None;
+        for part in input.split('/') {
+            let (slot, counts) = match part.chars().next() {
+                Some('B') | Some('b') => (&mut born, &part[1..]),
+                Some('S') | Some('s') => (&mut survives, &part[1..]),
+                _ => bail!("Rule part {:?} isn't B or S.", part),
+            };
+            if slot.is_some() {
+                bail!("Rule {} has more than one {}.", input, &part[..1]);
             }
+            *slot = Some(parse_counts(counts)?);
+        }
+        let born = born.context("Rule is missing the B part.")?;
+        let survives = survives.context("Rule is missing the S part.")?;
+        if born.contains(&0) {
+            // Every inactive cube in infinite space would become active.
+            bail!("Rules with B0 can't be simulated.");
         }
-        neighbors
+        Ok(Self { born, survives })
     }
 }
 
-fn main() {
-    let active_cubes: Cubes = read_entries::<String>("./data/day-17.txt")
-        .enumerate()
-        .map(|(x, row)| {
-            row.chars()
-                .enumerate()
-                .filter(|(_, c)| *c == '#')
-                .map(|(y, _)| (x as isize, y as isize, 1))
+impl Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = |counts: &BTreeSet<usize>| {
+            let separator = if counts.iter().any(|&c| c > 9) {
+                ","
+            } else {
+                ""
+            };
+            counts
+                .iter()
+                .map(|c| c.to_string())
                 .collect::<Vec<_>>()
+                .join(separator)
+        };
+        write!(f, "B{}/S{}", counts(&self.born), counts(&self.survives))
+    }
+}
+
+trait HasNeighbors: Sized + Clone + Eq + Hash {
+    fn neighbors(&self) -> Vec<Self>;
+}
+
+// A point in D dimensional space, for every D at once.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct Point<const D: usize>([isize; D]);
+
+impl<const D: usize> Point<D> {
+    // Places a point of the 2D input in the first two dimensions, every
+    // other dimension starts at 0.
+    fn from_plane(x: isize, y: isize) -> Self {
+        let mut coords = [0; D];
+        coords[0] = x;
+        coords[1] = y;
+        Point(coords)
+    }
+}
+
+impl<const D: usize> HasNeighbors for Point<D> {
+    fn neighbors(&self) -> Vec<Self> {
+        offsets::<D>()
+            .map(|offset| {
+                let mut coords = self.0;
+                for (coord, delta) in coords.iter_mut().zip(&offset) {
+                    *coord += delta;
+                }
+                Point(coords)
+            })
+            .collect()
+    }
+}
+
+// Every step to a neighbour in D dimensions. Each of the 3^D offsets is a D
+// digit number in base 3, with the digits 0, 1 and 2 standing for -1, 0 and
+// +1.
+fn offsets<const D: usize>() -> impl Iterator<Item = [isize; D]> {
+    (0..3_usize.pow(D as u32))
+        .map(|offset| {
+            let mut deltas = [0; D];
+            let mut rest = offset;
+            for delta in deltas.iter_mut() {
+                *delta = (rest % 3) as isize - 1;
+                rest /= 3;
+            }
+            deltas
         })
-        .flatten()
-        .collect();
+        .filter(|deltas| deltas.iter().any(|&d| d != 0))
+}
+
+// The whole region around the active cubes as one flat array. All the
+// dimensions past the first two start out as a single layer at 0, so the
+// space stays mirror symmetric in each of them and only their non-negative
+// half is stored.
+struct DenseGrid<const D: usize> {
+    // The lowest x and y held, extra dimensions are always held from 0.
+    origin: [isize; 2],
+    shape: [usize; D],
+    cells: Vec<bool>,
+}
+
+impl<const D: usize> DenseGrid<D> {
+    fn from_plane(plane: &[(isize, isize)]) -> Self {
+        let points: Vec<[isize; D]> = plane
+            .iter()
+            .map(|&(x, y)| Point::<D>::from_plane(x, y).0)
+            .collect();
+        Self::around(&points)
+    }
+
+    // The smallest grid holding the given points, none of which may have a
+    // negative extra coordinate.
+    fn around(points: &[[isize; D]]) -> Self {
+        let mut low = [0; 2];
+        let mut high = [-1; D];
+        for (i, point) in points.iter().enumerate() {
+            for d in 0..D {
+                if d < 2 && (i == 0 || point[d] < low[d]) {
+                    low[d] = point[d];
+                }
+                if i == 0 || point[d] > high[d] {
+                    high[d] = point[d];
+                }
+            }
+        }
+        let mut shape = [0; D];
+        for d in 0..D {
+            let start = if d < 2 { low[d] } else { 0 };
+            shape[d] = (high[d] - start + 1).max(0) as usize;
+        }
+        let mut grid = Self {
+            origin: low,
+            shape,
+            cells: vec![false; shape.iter().product()],
+        };
+        for point in points {
+            let index = grid.index(point).expect("Point is in its own grid.");
+            grid.cells[index] = true;
+        }
+        grid
+    }
+
+    fn index(&self, point: &[isize; D]) -> Option<usize> {
+        let mut index = 0;
+        for (d, &coord) in point.iter().enumerate() {
+            let offset = if d < 2 {
+                coord - self.origin[d]
+            } else {
+                coord.abs()
+            };
+            if offset < 0 || offset as usize >= self.shape[d] {
+                return None;
+            }
+            index = index * self.shape[d] + offset as usize;
+        }
+        Some(index)
+    }
+
+    fn point(&self, mut index: usize) -> [isize; D] {
+        let mut point = [0; D];
+        for d in (0..D).rev() {
+            let offset = (index % self.shape[d]) as isize;
+            index /= self.shape[d];
+            point[d] = if d < 2 {
+                offset + self.origin[d]
+            } else {
+                offset
+            };
+        }
+        point
+    }
+
+    fn is_active(&self, point: &[isize; D]) -> bool {
+        self.index(point).is_some_and(|index| self.cells[index])
+    }
 
-    let active_hypercubes: HyperCubes = active_cubes
+    fn cycle(&self, offsets: &[[isize; D]], rule: &Rule) -> Self {
+        // Anything that can become active is at most one step outside the
+        // current grid.
+        let mut bounds = Self {
+            origin: [self.origin[0] - 1, self.origin[1] - 1],
+            shape: self.shape,
+            cells: vec![],
+        };
+        for (d, size) in bounds.shape.iter_mut().enumerate() {
+            *size += if d < 2 { 2 } else { 1 };
+        }
+
+        let total: usize = bounds.shape.iter().product();
+        let active: Vec<[isize; D]> = (0..total)
+            .map(|index| bounds.point(index))
+            .filter(|point| {
+                let mut neighbor = *point;
+                let count = offsets
+                    .iter()
+                    .filter(|offset| {
+                        for d in 0..D {
+                            neighbor[d] = point[d] + offset[d];
+                        }
+                        self.is_active(&neighbor)
+                    })
+                    .count();
+                rule.next_state(self.is_active(point), count)
+            })
+            .collect();
+        Self::around(&active)
+    }
+
+    // A stored cube stands for 2 cubes for every extra dimension in which
+    // it's off the mirror plane.
+    fn active_count(&self) -> usize {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, &active)| active)
+            .map(|(index, _)| {
+                let point = self.point(index);
+                1 << point[2..].iter().filter(|&&c| c != 0).count()
+            })
+            .sum()
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let dimensions = match flag_value(&args, "--dimensions") {
+        Some(dimensions) => vec![dimensions
+            .context("--dimensions needs a number.")?
+            .parse::<usize>()
+            .context("Unparseable dimensions.")?],
+        None => vec![3, 4],
+    };
+
+    let plane: Vec<(isize, isize)> =
+        read_entries::<String>("./data/day-17.txt")
+            .enumerate()
+            .flat_map(|(x, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '#')
+                    .map(|(y, _)| (x as isize, y as isize))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+    let rule = match flag_value(&args, "--rule") {
+        Some(rule) => rule
+            .context("--rule needs a rulestring.")?
+            .parse::<Rule>()?,
+        None => Rule::default(),
+    };
+
+    let sparse = args.iter().any(|arg| arg == "--sparse");
+    for dimensions in dimensions {
+        let active = match (dimensions, sparse) {
+            (2, false) => simulate_dense::<2>(&plane, 6, &rule),
+            (3, false) => simulate_dense::<3>(&plane, 6, &rule),
+            (4, false) => simulate_dense::<4>(&plane, 6, &rule),
+            (5, false) => simulate_dense::<5>(&plane, 6, &rule),
+            (6, false) => simulate_dense::<6>(&plane, 6, &rule),
+            (2, true) => simulate::<2>(&plane, 6, &rule),
+            (3, true) => simulate::<3>(&plane, 6, &rule),
+            (4, true) => simulate::<4>(&plane, 6, &rule),
+            (5, true) => simulate::<5>(&plane, 6, &rule),
+            (6, true) => simulate::<6>(&plane, 6, &rule),
+            (hm, _) => {
+                bail!("Can only simulate 2 to 6 dimensions, not {}.", hm)
+            }
+        };
+        println!("Active cubes ({}D): {}", dimensions, active);
+    }
+    Ok(())
+}
+
+fn simulate<const D: usize>(
+    plane: &[(isize, isize)],
+    cycles: usize,
+    rule: &Rule,
+) -> usize {
+    let active_cubes = plane
         .iter()
-        .cloned()
-        .map(|(x, y, z)| (x, y, z, 1))
+        .map(|&(x, y)| Point::<D>::from_plane(x, y))
         .collect();
+    run_cycles(active_cubes, cycles, rule)
+}
 
-    println!("Active cubes: {:?}", run_cycles(active_cubes, 6));
-    println!("Active cubes: {:?}", run_cycles(active_hypercubes, 6));
+fn simulate_dense<const D: usize>(
+    plane: &[(isize, isize)],
+    cycles: usize,
+    rule: &Rule,
+) -> usize {
+    let offsets: Vec<_> = offsets::<D>().collect();
+    let mut grid = DenseGrid::<D>::from_plane(plane);
+    for _ in 0..cycles {
+        grid = grid.cycle(&offsets, rule);
+    }
+    grid.active_count()
 }
 
 fn run_cycles(
     mut active_cubes: HashSet<impl HasNeighbors>,
     cycles: usize,
+    rule: &Rule,
 ) -> usize {
     for _ in 0..cycles {
-        active_cubes = cycle(active_cubes);
+        active_cubes = cycle(active_cubes, rule);
     }
     active_cubes.len()
 }
 
-fn cycle<T: HasNeighbors>(active_cubes: HashSet<T>) -> HashSet<T> {
-    let remain_active = active_cubes
-        .iter()
-        .cloned()
-        .filter(|c| {
-            let active_count = active_neighbors(c, &active_cubes);
-            active_count == 2 || active_count == 3
-        })
-        .collect::<HashSet<_>>();
+fn cycle<T: HasNeighbors>(active_cubes: HashSet<T>, rule: &Rule) -> HashSet<T> {
+    // Tally how many active neighbours every cube next to an active one
+    // has, rather than looking each neighbourhood up. Active cubes go in
+    // too, as they can survive without any.
+    let mut active_neighbors: HashMap<T, usize> = HashMap::new();
+    for cube in &active_cubes {
+        active_neighbors.entry(cube.clone()).or_insert(0);
+        for neighbor in cube.neighbors() {
+            *active_neighbors.entry(neighbor).or_insert(0) += 1;
+        }
+    }
 
-    let mut activated = active_cubes
-        .iter()
-        .map(HasNeighbors::neighbors)
-        .flatten()
-        .filter(|c| {
-            !active_cubes.contains(&c)
-                && active_neighbors(c, &active_cubes) == 3
+    active_neighbors
+        .into_iter()
+        .filter(|(cube, count)| {
+            rule.next_state(active_cubes.contains(cube), *count)
         })
-        .collect::<HashSet<_>>();
-
-    activated.extend(remain_active);
-    activated
-}
-
-fn active_neighbors<T: HasNeighbors>(
-    cube: &T,
-    active_cubes: &HashSet<T>,
-) -> usize {
-    cube.neighbors().intersection(active_cubes).count()
+        .map(|(cube, _)| cube)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const EXAMPLE: [(isize, isize); 5] =
+        [(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)];
+
     #[test]
     fn example() {
-        let active_cubes =
-            [(0, 1, 1), (1, 2, 1), (2, 0, 1), (2, 1, 1), (2, 2, 1)]
-                .iter()
-                .cloned()
-                .collect();
-        assert_eq!(112, run_cycles(active_cubes, 6));
+        let rule = Rule::default();
+        assert_eq!(112, simulate::<3>(&EXAMPLE, 6, &rule));
+    }
+
+    #[test]
+    fn example_4d() {
+        let rule = Rule::default();
+        assert_eq!(848, simulate::<4>(&EXAMPLE, 6, &rule));
+    }
+
+    #[test]
+    fn dense_examples() {
+        let rule = Rule::default();
+        assert_eq!(112, simulate_dense::<3>(&EXAMPLE, 6, &rule));
+        assert_eq!(848, simulate_dense::<4>(&EXAMPLE, 6, &rule));
+        for cycles in 0..4 {
+            assert_eq!(
+                simulate::<2>(&EXAMPLE, cycles, &rule),
+                simulate_dense::<2>(&EXAMPLE, cycles, &rule)
+            );
+            assert_eq!(
+                simulate::<5>(&EXAMPLE, cycles, &rule),
+                simulate_dense::<5>(&EXAMPLE, cycles, &rule)
+            );
+        }
+    }
+
+    #[test]
+    fn rulestrings() {
+        let rule: Rule = "B3/S23".parse().unwrap();
+        assert_eq!(Rule::default(), rule);
+        assert_eq!(112, simulate::<3>(&EXAMPLE, 6, &rule));
+        assert_eq!("S23/B3".parse::<Rule>().unwrap(), rule);
+        assert_eq!("b3/s23".parse::<Rule>().unwrap(), rule);
+
+        let wide: Rule = "B5,13/S4,5,13".parse().unwrap();
+        assert_eq!("B5,13/S4,5,13", wide.to_string());
+        assert_eq!("B36/S23", "B63/S32".parse::<Rule>().unwrap().to_string());
+        assert_eq!("B3/S", "B3/S".parse::<Rule>().unwrap().to_string());
+
+        assert!("B3".parse::<Rule>().is_err());
+        assert!("B3/S23/B4".parse::<Rule>().is_err());
+        assert!("B3/X23".parse::<Rule>().is_err());
+        assert!("B0/S23".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn other_rules() {
+        // A lone cube survives with S0, and nothing is ever born with B/S0
+        // other than in the starting plane.
+        let lone = [(0, 0)];
+        let still: Rule = "B/S0".parse().unwrap();
+        assert_eq!(1, simulate::<3>(&lone, 3, &still));
+        assert_eq!(1, simulate_dense::<3>(&lone, 3, &still));
+
+        // HighLife in 2D, the two backends agree in every dimension.
+        let high_life: Rule = "B36/S23".parse().unwrap();
+        for cycles in 0..4 {
+            assert_eq!(
+                simulate::<2>(&EXAMPLE, cycles, &high_life),
+                simulate_dense::<2>(&EXAMPLE, cycles, &high_life)
+            );
+            assert_eq!(
+                simulate::<4>(&EXAMPLE, cycles, &high_life),
+                simulate_dense::<4>(&EXAMPLE, cycles, &high_life)
+            );
+        }
+    }
+
+    #[test]
+    fn neighbor_counts() {
+        assert_eq!(8, Point::<2>::from_plane(0, 0).neighbors().len());
+        assert_eq!(26, Point::<3>::from_plane(0, 0).neighbors().len());
+        assert_eq!(728, Point::<6>::from_plane(0, 0).neighbors().len());
     }
 }
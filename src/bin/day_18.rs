@@ -1,137 +1,635 @@
-use anyhow::{Context, Result};
-use aoc_2020::read_entries;
+use anyhow::{bail, Context, Result};
+use aoc_2020::{flag_value, read_entries};
+use num::{
+    traits::checked_pow, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Num,
+    ToPrimitive,
+};
+use num_bigint::BigInt;
+use std::{
+    fmt::{self, Display},
+    iter::Peekable,
+    vec::IntoIter,
+};
+
+// A type expressions can be evaluated in, where every operation reports
+// overflowing instead of wrapping.
+trait Numeric:
+    Num
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + Clone
+    + ToPrimitive
+    + Display
+{
+    fn checked_negate(&self) -> Option<Self>;
+
+    // Whether raising to `exponent` stays within what the type can hold.
+    // Fixed size types find out cheaply by overflowing.
+    fn fits_power(&self, _exponent: usize) -> bool {
+        true
+    }
+}
+
+impl Numeric for u64 {
+    fn checked_negate(&self) -> Option<Self> {
+        self.checked_neg()
+    }
+}
+
+impl Numeric for i64 {
+    fn checked_negate(&self) -> Option<Self> {
+        self.checked_neg()
+    }
+}
+
+// The most bits a power is allowed to take up, much more would take
+// forever to compute or run out of memory.
+const MAX_POWER_BITS: u64 = 1 << 20;
+
+impl Numeric for BigInt {
+    fn checked_negate(&self) -> Option<Self> {
+        Some(-self)
+    }
+
+    fn fits_power(&self, exponent: usize) -> bool {
+        // 0, 1 and -1 stay small whatever the exponent.
+        let bits = self.bits();
+        bits <= 1
+            || (exponent as u64)
+                .checked_mul(bits - 1)
+                .is_some_and(|at_least| at_least <= MAX_POWER_BITS)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    // The digits as written, they're only turned into a number once it's
+    // known what kind of number.
+    Number(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+// Splits an expression into tokens, each with the (1 based) column it
+// starts at.
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let column = i + 1;
+        let token = match c {
+            ' ' => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '+' | '-' | '*' | '/' | '^' => Token::Op(c),
+            '0'..='9' => {
+                let mut end = i + 1;
+                while let Some(&(j, '0'..='9')) = chars.peek() {
+                    end = j + 1;
+                    chars.next();
+                }
+                Token::Number(input[i..end].to_string())
+            }
+            _ => bail!("Unexpected {:?} at column {}.", c, column),
+        };
+        tokens.push((column, token));
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+// How tightly each operator binds, higher binds tighter, and which way a
+// chain of operators with the same precedence groups.
+struct PrecedenceTable {
+    binary: Vec<(char, u8, Associativity)>,
+    // Unary minus, if it's allowed at all.
+    negation: Option<u8>,
+}
+
+impl PrecedenceTable {
+    // Everything evaluated left to right.
+    fn equal() -> Self {
+        use Associativity::Left;
+        Self {
+            binary: vec![
+                ('+', 1, Left),
+                ('-', 1, Left),
+                ('*', 1, Left),
+                ('/', 1, Left),
+                ('^', 1, Left),
+            ],
+            negation: Some(2),
+        }
+    }
+
+    fn addition_first() -> Self {
+        use Associativity::{Left, Right};
+        Self {
+            binary: vec![
+                ('+', 2, Left),
+                ('-', 2, Left),
+                ('*', 1, Left),
+                ('/', 1, Left),
+                ('^', 4, Right),
+            ],
+            negation: Some(3),
+        }
+    }
+
+    // The usual maths rules, where -2^2 is -4.
+    fn standard() -> Self {
+        use Associativity::{Left, Right};
+        Self {
+            binary: vec![
+                ('+', 1, Left),
+                ('-', 1, Left),
+                ('*', 2, Left),
+                ('/', 2, Left),
+                ('^', 4, Right),
+            ],
+            negation: Some(3),
+        }
+    }
+
+    fn binary(&self, op: char) -> Option<(u8, Associativity)> {
+        self.binary
+            .iter()
+            .find(|(known, _, _)| *known == op)
+            .map(|&(_, precedence, associativity)| (precedence, associativity))
+    }
+}
+
+struct Parser<'a> {
+    tokens: Peekable<IntoIter<(usize, Token)>>,
+    table: &'a PrecedenceTable,
+    // The column just past the input, for errors about it ending early.
+    end: usize,
+}
+
+impl Parser<'_> {
+    // Precedence climbing, parses operands and any operators binding at
+    // least as tightly as `min_precedence`. That's wider than the table's
+    // precedences so that one more than the highest still fits.
+    fn expression(&mut self, min_precedence: u16) -> Result<Expression> {
+        let mut left = self.operand()?;
+        while let Some(&(column, Token::Op(op))) = self.tokens.peek() {
+            let (precedence, associativity) =
+                self.table.binary(op).with_context(|| {
+                    format!("Unknown operator {} at column {}.", op, column)
+                })?;
+            let precedence = u16::from(precedence);
+            if precedence < min_precedence {
+                break;
+            }
+            self.tokens.next();
+            // A left associative operator's right hand side only takes
+            // operators binding tighter, a right associative one takes
+            // itself too.
+            let right = Box::new(match associativity {
+                Associativity::Left => self.expression(precedence + 1)?,
+                Associativity::Right => self.expression(precedence)?,
+            });
+            let left_box = Box::new(left);
+            left = match op {
+                '+' => Expression::Add(left_box, right),
+                '-' => Expression::Sub(left_box, right),
+                '*' => Expression::Mul(left_box, right),
+                '/' => Expression::Div(left_box, right),
+                _ => Expression::Pow(left_box, right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Expression> {
+        match self.tokens.next() {
+            Some((_, Token::Number(val))) => Ok(Expression::Number(val)),
+            Some((column, Token::Op('-'))) => {
+                let precedence = self.table.negation.with_context(|| {
+                    format!("Unexpected unary - at column {}.", column)
+                })?;
+                let inner = self.expression(u16::from(precedence))?;
+                Ok(Expression::Neg(Box::new(inner)))
+            }
+            Some((_, Token::Open)) => {
+                let inner = self.expression(0)?;
+                match self.tokens.next() {
+                    Some((_, Token::Close)) => {
+                        Ok(Expression::Paren(Box::new(inner)))
+                    }
+                    Some((column, token)) => bail!(
+                        "Expected ) but found {:?} at column {}.",
+                        token,
+                        column
+                    ),
+                    None => bail!("Missing ) at column {}.", self.end),
+                }
+            }
+            Some((column, token)) => {
+                bail!("Unexpected {:?} at column {}.", token, column)
+            }
+            None => bail!("Unexpected end at column {}.", self.end),
+        }
+    }
+}
 
 #[derive(Debug)]
 enum Expression {
     Add(Box<Expression>, Box<Expression>),
+    Sub(Box<Expression>, Box<Expression>),
     Mul(Box<Expression>, Box<Expression>),
+    Div(Box<Expression>, Box<Expression>),
+    Pow(Box<Expression>, Box<Expression>),
+    Neg(Box<Expression>),
     Paren(Box<Expression>),
-    Digit(usize),
+    Number(String),
 }
 
 impl Expression {
-    fn parse(expression: &[char], op: Option<char>) -> Result<Self> {
-        // println!("Parsing: {}", expression.iter().collect::<String>());
-        let parsed = match expression {
-            [c] => Expression::Digit(
-                c.to_digit(10).context("Should be a digit.")? as usize,
-            ),
-            ['(', inner @ .., ')'] if balanced_parens(inner) => {
-                Expression::Paren(Box::new(Expression::parse(&inner, op)?))
+    fn parse(input: &str, table: &PrecedenceTable) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?.into_iter().peekable(),
+            table,
+            end: input.len() + 1,
+        };
+        let parsed = parser.expression(0)?;
+        if let Some((column, token)) = parser.tokens.next() {
+            bail!("Unexpected {:?} at column {}.", token, column);
+        }
+        Ok(parsed)
+    }
+
+    fn evaluate<N: Numeric>(&self) -> Result<N> {
+        let val = match self {
+            Expression::Add(left, right) => {
+                left.evaluate::<N>()?.checked_add(&right.evaluate()?)
             }
-            _ => {
-                let opi = find_last_op(&expression, op)
-                    .or_else(|| find_last_op(&expression, None))
-                    .context("Failed to find an operator!")?;
-                let left = Box::new(Expression::parse(&expression[..opi], op)?);
-                let right =
-                    Box::new(Expression::parse(&expression[opi + 1..], op)?);
-                if expression[opi] == '*' {
-                    Expression::Mul(left, right)
-                } else {
-                    Expression::Add(left, right)
+            Expression::Sub(left, right) => {
+                left.evaluate::<N>()?.checked_sub(&right.evaluate()?)
+            }
+            Expression::Mul(left, right) => {
+                left.evaluate::<N>()?.checked_mul(&right.evaluate()?)
+            }
+            Expression::Div(left, right) => {
+                let divisor: N = right.evaluate()?;
+                if divisor.is_zero() {
+                    bail!("Division by zero in {}.", self);
                 }
+                left.evaluate::<N>()?.checked_div(&divisor)
             }
+            Expression::Pow(left, right) => {
+                let exponent: N = right.evaluate()?;
+                let exponent = exponent.to_usize().with_context(|| {
+                    format!("Can't raise to the power of {}.", exponent)
+                })?;
+                let base: N = left.evaluate()?;
+                if !base.fits_power(exponent) {
+                    bail!("{} is too large to compute.", self);
+                }
+                checked_pow(base, exponent)
+            }
+            Expression::Neg(inner) => inner.evaluate::<N>()?.checked_negate(),
+            Expression::Paren(inner) => Some(inner.evaluate()?),
+            Expression::Number(digits) => N::from_str_radix(digits, 10).ok(),
         };
-        Ok(parsed)
+        val.with_context(|| format!("{} overflows.", self))
     }
 
-    fn evaluate(&self) -> usize {
+    // The operator and operands of a node, if it has any.
+    fn parts(&self) -> (String, Vec<&Expression>) {
         match self {
-            Expression::Add(left, right) => left.evaluate() + right.evaluate(),
-            Expression::Mul(left, right) => left.evaluate() * right.evaluate(),
-            Expression::Paren(inner) => inner.evaluate(),
-            Expression::Digit(val) => *val,
+            Expression::Add(left, right) => ("+".into(), vec![left, right]),
+            Expression::Sub(left, right) => ("-".into(), vec![left, right]),
+            Expression::Mul(left, right) => ("*".into(), vec![left, right]),
+            Expression::Div(left, right) => ("/".into(), vec![left, right]),
+            Expression::Pow(left, right) => ("^".into(), vec![left, right]),
+            Expression::Neg(inner) => ("-".into(), vec![inner]),
+            Expression::Paren(inner) => ("()".into(), vec![inner]),
+            Expression::Number(digits) => (digits.clone(), vec![]),
         }
     }
-}
 
-fn balanced_parens(chars: &[char]) -> bool {
-    let mut parens = 0;
-    for &c in chars {
-        if c == '(' {
-            parens += 1
-        }
-        if c == ')' {
-            parens -= 1
-        }
-        if parens < 0 {
-            return false;
+    // The tree one node per line, each operand indented under its operator.
+    fn dump(&self) -> String {
+        let mut lines = vec![];
+        let mut stack = vec![(0, self)];
+        while let Some((depth, node)) = stack.pop() {
+            let (label, operands) = node.parts();
+            lines.push(format!("{}{}", "  ".repeat(depth), label));
+            stack.extend(operands.into_iter().rev().map(|op| (depth + 1, op)));
         }
+        lines.join("\n")
     }
-    parens == 0
 }
 
-fn find_last_op(chars: &[char], op: Option<char>) -> Option<usize> {
-    let mut parens = 0;
-    for i in (0..chars.len()).rev() {
-        match chars[i] {
-            '(' => parens += 1,
-            ')' => parens -= 1,
-            c @ '*' | c @ '+'
-                if (op.is_none() || Some(c) == op) && parens == 0 =>
-            {
-                return Some(i)
+// Every operator with its operands in parentheses, so it reads the same
+// whatever the precedence table.
+impl Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Paren(inner) => write!(f, "{}", inner),
+            Expression::Neg(inner) => write!(f, "(-{})", inner),
+            Expression::Number(digits) => write!(f, "{}", digits),
+            _ => {
+                let (op, operands) = self.parts();
+                write!(f, "({} {} {})", operands[0], op, operands[1])
             }
-            _ => {}
         }
     }
-    None
 }
 
-fn main() {
-    let expressions = read_entries::<String>("./data/day-18.txt")
-        .map(|e| e.chars().filter(|c| c != &' ').collect::<Vec<_>>())
-        .collect::<Vec<_>>();
-
-    let sum: usize = expressions
+fn sum_all<N: Numeric>(
+    expressions: &[String],
+    table: &PrecedenceTable,
+) -> Result<N> {
+    expressions
         .iter()
-        .map(|e| Expression::parse(e, None).unwrap().evaluate())
-        .sum();
+        .enumerate()
+        .try_fold(N::zero(), |sum, (line, e)| {
+            let val = Expression::parse(e, table)
+                .and_then(|parsed| parsed.evaluate::<N>())
+                .with_context(|| format!("Line {}: {}", line + 1, e))?;
+            sum.checked_add(&val).context("The sum overflows.")
+        })
+}
 
-    println!("Sum is {}", sum);
+fn main() -> Result<()> {
+    let expressions =
+        read_entries::<String>("./data/day-18.txt").collect::<Vec<_>>();
 
-    let sum: usize = expressions
-        .iter()
-        .map(|e| Expression::parse(e, Some('*')).unwrap().evaluate())
-        .sum();
+    let args: Vec<String> = std::env::args().collect();
+    let dump = flag_value(&args, "--dump")
+        .map(|line| {
+            line.context("--dump needs a line number.")?
+                .parse::<usize>()
+                .context("Unparseable line number.")
+        })
+        .transpose()?;
+    match flag_value(&args, "--numbers") {
+        Some(Some("u64")) => report::<u64>(&expressions, dump),
+        Some(Some("i64")) | None => report::<i64>(&expressions, dump),
+        Some(Some("big")) => report::<BigInt>(&expressions, dump),
+        Some(_) => bail!("--numbers needs one of u64, i64 or big."),
+    }
+}
+
+fn report<N: Numeric>(
+    expressions: &[String],
+    dump: Option<usize>,
+) -> Result<()> {
+    if let Some(line) = dump {
+        let expression =
+            line.checked_sub(1)
+                .and_then(|i| expressions.get(i))
+                .with_context(|| format!("There's no line {}.", line))?;
+        for (name, table) in &[
+            ("Equal", PrecedenceTable::equal()),
+            ("Addition first", PrecedenceTable::addition_first()),
+            ("Standard", PrecedenceTable::standard()),
+        ] {
+            let parsed = Expression::parse(expression, table)?;
+            println!("{}: {} = {}", name, parsed, parsed.evaluate::<N>()?);
+            println!("{}", parsed.dump());
+        }
+        return Ok(());
+    }
+
+    let sum: N = sum_all(expressions, &PrecedenceTable::equal())?;
+    println!("Sum is {}", sum);
 
+    let sum: N = sum_all(expressions, &PrecedenceTable::addition_first())?;
     println!("Sum with addition precedence is {}", sum);
+
+    let sum: N = sum_all(expressions, &PrecedenceTable::standard())?;
+    println!("Sum with standard precedence is {}", sum);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const EXAMPLE: &str = "((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2";
+
+    fn evaluate(input: &str, table: &PrecedenceTable) -> i64 {
+        Expression::parse(input, table).unwrap().evaluate().unwrap()
+    }
+
     #[test]
     fn examples() {
-        assert_eq!(
-            Expression::parse(
-                &"((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2"
-                    .chars()
-                    .filter(|c| c != &' ')
-                    .collect::<Vec<_>>(),
-                None
-            )
-            .unwrap()
-            .evaluate(),
-            13632
-        )
+        assert_eq!(evaluate(EXAMPLE, &PrecedenceTable::equal()), 13632)
     }
 
     #[test]
     fn examples_advanced() {
+        assert_eq!(evaluate(EXAMPLE, &PrecedenceTable::addition_first()), 23340)
+    }
+
+    #[test]
+    fn multi_digit() {
+        let table = PrecedenceTable::equal();
+        assert_eq!(evaluate("12 * 10 + 345", &table), 465);
+        assert_eq!(evaluate("(100)", &table), 100);
+        assert_eq!(
+            tokenize("12*(3)").unwrap(),
+            vec![
+                (1, Token::Number("12".into())),
+                (3, Token::Op('*')),
+                (4, Token::Open),
+                (5, Token::Number("3".into())),
+                (6, Token::Close),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_columns() {
+        let table = PrecedenceTable::equal();
+        let error =
+            |input| Expression::parse(input, &table).unwrap_err().to_string();
+        assert_eq!(error("1 + x"), "Unexpected 'x' at column 5.");
+        assert_eq!(error("1 + * 2"), "Unexpected Op('*') at column 5.");
+        assert_eq!(error("(1 + 2"), "Missing ) at column 7.");
+        assert_eq!(
+            error("(1 + 2 3"),
+            "Expected ) but found Number(\"3\") at column 8."
+        );
+        assert_eq!(error("1 + 2)"), "Unexpected Close at column 6.");
+        assert_eq!(error("1 +"), "Unexpected end at column 4.");
+    }
+
+    #[test]
+    fn tables() {
+        let equal = PrecedenceTable::equal();
+        let addition_first = PrecedenceTable::addition_first();
+        let standard = PrecedenceTable::standard();
+        assert_eq!(evaluate("2 + 3 * 4 - 5", &equal), 15);
+        assert_eq!(evaluate("2 + 3 * 4 - 5", &addition_first), -5);
+        assert_eq!(evaluate("2 + 3 * 4 - 5", &standard), 9);
+
+        assert_eq!(evaluate("10 - 4 - 3", &standard), 3);
+        assert_eq!(evaluate("100 / 10 / 5", &standard), 2);
+        assert_eq!(evaluate("2 ^ 3 ^ 2", &standard), 512);
+        assert_eq!(evaluate("2 ^ 3 ^ 2", &equal), 64);
+        assert_eq!(evaluate("7 / 2", &standard), 3);
+    }
+
+    #[test]
+    fn unary_minus() {
+        let equal = PrecedenceTable::equal();
+        let standard = PrecedenceTable::standard();
+        assert_eq!(evaluate("-2 ^ 2", &standard), -4);
+        assert_eq!(evaluate("-2 ^ 2", &equal), 4);
+        assert_eq!(evaluate("-2 * 3", &standard), -6);
+        assert_eq!(evaluate("2 - -3", &standard), 5);
+        assert_eq!(evaluate("--3", &standard), 3);
+        assert_eq!(evaluate("-(1 + 2) * 2", &standard), -6);
+
+        let no_negation = PrecedenceTable {
+            negation: None,
+            ..PrecedenceTable::standard()
+        };
+        assert_eq!(
+            Expression::parse("1 + -2", &no_negation)
+                .unwrap_err()
+                .to_string(),
+            "Unexpected unary - at column 5."
+        );
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let standard = PrecedenceTable::standard();
+        let parsed = |input| Expression::parse(input, &standard).unwrap();
+        assert!(parsed("1 / (2 - 2)").evaluate::<i64>().is_err());
+        assert!(parsed("2 ^ -1").evaluate::<i64>().is_err());
+    }
+
+    #[test]
+    fn printing() {
+        let standard = PrecedenceTable::standard();
+        let parsed = Expression::parse("-(1 + 2) * 3 ^ 2", &standard).unwrap();
+        assert_eq!(parsed.to_string(), "((-(1 + 2)) * (3 ^ 2))");
+        assert_eq!(
+            parsed.dump(),
+            "*\n  -\n    ()\n      +\n        1\n        2\n  ^\n    3\n    2"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let tables = [
+            PrecedenceTable::equal(),
+            PrecedenceTable::addition_first(),
+            PrecedenceTable::standard(),
+        ];
+        for input in &[EXAMPLE, "2 ^ 3 ^ 2 - -4 / 2", "-2 ^ 2 * (3 - 10)"] {
+            for table in &tables {
+                let parsed = Expression::parse(input, table).unwrap();
+                let printed = parsed.to_string();
+                // Fully parenthesised, the printed form means the same under
+                // every table.
+                for other in &tables {
+                    assert_eq!(
+                        evaluate(&printed, other),
+                        parsed.evaluate::<i64>().unwrap(),
+                        "{} from {}",
+                        printed,
+                        input
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        let standard = PrecedenceTable::standard();
+        let parsed = |input| Expression::parse(input, &standard).unwrap();
+
+        let long = parsed("4294967296 * 4294967296 * 3");
+        assert_eq!(
+            long.evaluate::<u64>().unwrap_err().to_string(),
+            "(4294967296 * 4294967296) overflows."
+        );
+        assert!(long.evaluate::<i64>().is_err());
+        assert_eq!(
+            long.evaluate::<BigInt>().unwrap(),
+            BigInt::from(3) << 64_usize
+        );
+
+        // Negative results only fit signed numbers.
+        assert!(parsed("2 - 3").evaluate::<u64>().is_err());
+        assert_eq!(parsed("2 - 3").evaluate::<i64>().unwrap(), -1);
+        assert_eq!(parsed("-0").evaluate::<u64>().unwrap(), 0);
+        assert!(parsed("2 ^ 63").evaluate::<i64>().is_err());
+        assert_eq!(parsed("2 ^ 63").evaluate::<u64>().unwrap(), 1 << 63);
         assert_eq!(
-            Expression::parse(
-                &"((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2"
-                    .chars()
-                    .filter(|c| c != &' ')
-                    .collect::<Vec<_>>(),
-                Some('*')
-            )
-            .unwrap()
-            .evaluate(),
-            23340
-        )
+            parsed("-2 ^ 100").evaluate::<BigInt>().unwrap(),
+            -(BigInt::from(1) << 100_usize)
+        );
+    }
+
+    #[test]
+    fn sums() {
+        let lines = vec![EXAMPLE.to_string(), "2 * 3 + (4 * 5)".to_string()];
+        let table = PrecedenceTable::equal();
+        assert_eq!(sum_all::<u64>(&lines, &table).unwrap(), 13632 + 26);
+        assert_eq!(
+            sum_all::<BigInt>(&lines, &table).unwrap(),
+            BigInt::from(13632 + 26)
+        );
+        let lines = vec!["9223372036854775807".to_string(), "1".to_string()];
+        assert!(sum_all::<i64>(&lines, &table).is_err());
+        assert!(sum_all::<u64>(&lines, &table).is_ok());
+    }
+
+    #[test]
+    fn big_literals() {
+        let standard = PrecedenceTable::standard();
+        let parsed = |input| Expression::parse(input, &standard).unwrap();
+
+        let literal = "123456789012345678901234567890";
+        assert!(parsed(literal).evaluate::<u64>().is_err());
+        let sum = format!("{} + 1", literal);
+        assert_eq!(
+            parsed(&sum).evaluate::<BigInt>().unwrap(),
+            literal.parse::<BigInt>().unwrap() + 1
+        );
+        assert_eq!(parsed(literal).to_string(), literal);
+
+        assert_eq!(
+            parsed("2 ^ 99999999999999")
+                .evaluate::<BigInt>()
+                .unwrap_err()
+                .to_string(),
+            "(2 ^ 99999999999999) is too large to compute."
+        );
+        assert!(parsed("2 ^ 99999999999999").evaluate::<u64>().is_err());
+        assert_eq!(
+            parsed("1 ^ 99999999999999").evaluate::<BigInt>().unwrap(),
+            BigInt::from(1)
+        );
+        assert_eq!(
+            parsed("2 ^ 1000").evaluate::<BigInt>().unwrap(),
+            BigInt::from(1) << 1000_usize
+        );
+    }
+
+    #[test]
+    fn highest_precedence() {
+        let table = PrecedenceTable {
+            binary: vec![('-', u8::MAX, Associativity::Left)],
+            negation: None,
+        };
+        assert_eq!(evaluate("10 - 4 - 3", &table), 3);
     }
 }
@@ -17,3 +17,17 @@ where
             .expect("Failed to parse line.")
     })
 }
+
+// The value following the flag `name` in `args`, if the flag was passed.
+// Another flag isn't taken as the value, so that's None like a flag at the
+// end.
+pub fn flag_value<'a>(
+    args: &'a [String],
+    name: &str,
+) -> Option<Option<&'a str>> {
+    args.iter().position(|arg| arg == name).map(|i| {
+        args.get(i + 1)
+            .map(String::as_str)
+            .filter(|value| !value.starts_with("--"))
+    })
+}